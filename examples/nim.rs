@@ -43,7 +43,6 @@ impl GameState for NimState {
         let max = (TARGET_NUMBER - self.current_num).min(3);
 
         (1..=max)
-            .into_iter()
             .map(|n| NimMove {
                 start_player: !self.start_player,
                 nums: n,
@@ -72,11 +71,16 @@ impl GameState for NimState {
         }
     }
 
-    fn terminal_is_win(&self, condition: &Self::UserData) -> bool {
-        match condition {
+    fn terminal_reward(&self, condition: &Self::UserData) -> f64 {
+        let win = match condition {
             WinCondition::StartPlayer => self.start_player,
             WinCondition::NotStartPlayer => !self.start_player,
             WinCondition::Invalid => false,
+        };
+        if win {
+            1.0
+        } else {
+            0.0
         }
     }
 }
@@ -121,7 +125,7 @@ fn main() -> io::Result<()> {
     let mut game = NimState::default();
 
     loop {
-        let best_move = mcts.run_with_duration(game.clone(), chrono::TimeDelta::seconds(1));
+        let best_move = mcts.run_with_duration(game, chrono::TimeDelta::seconds(1));
 
         let best_move = best_move.join();
 
@@ -0,0 +1,49 @@
+//! Statistics that threads sharing a tree update without locking it, see `Parallelism::Tree`.
+//! Updates are relaxed: a thread may select with statistics that miss the results other threads
+//! are backpropagating at the same time, as it would with a lock taken a little earlier.
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// A visit count.
+#[derive(Debug, Default)]
+pub(crate) struct Count(AtomicU32);
+
+impl Count {
+    pub fn new(n: u32) -> Self {
+        Self(AtomicU32::new(n))
+    }
+
+    pub fn get(&self) -> u32 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn add(&self, n: u32) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn sub(&self, n: u32) {
+        self.0.fetch_sub(n, Ordering::Relaxed);
+    }
+}
+
+/// A sum of rewards, or a value recomputed from other statistics.
+#[derive(Debug, Default)]
+pub(crate) struct Reward(AtomicU64);
+
+impl Reward {
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn add(&self, value: f64) {
+        let _ = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + value).to_bits())
+            });
+    }
+}
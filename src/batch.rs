@@ -0,0 +1,172 @@
+use std::{
+    collections::HashMap,
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{evaluator::Evaluator, GameState};
+
+struct Pending<T, X> {
+    states: Vec<(usize, T)>,
+    results: HashMap<usize, Option<X>>,
+    next_ticket: usize,
+}
+
+/// Leaves waiting to be evaluated, shared by all search threads so that the evaluator is called
+/// with batches of states instead of one state at a time.
+pub(crate) struct BatchQueue<T, X> {
+    pending: Mutex<Pending<T, X>>,
+    ready: Condvar,
+    batch_size: usize,
+    timeout: Duration,
+}
+
+impl<T: GameState, X> BatchQueue<T, X> {
+    pub fn new(batch_size: usize, timeout: Duration) -> Self {
+        Self {
+            pending: Mutex::new(Pending {
+                states: Vec::new(),
+                results: HashMap::new(),
+                next_ticket: 0,
+            }),
+            ready: Condvar::new(),
+            batch_size,
+            timeout,
+        }
+    }
+
+    /// Queue `states` and wait for their estimates. Whichever thread fills the batch, or has
+    /// waited longer than the timeout, evaluates everything that is pending with its own
+    /// evaluator and hands the results back to the other threads.
+    pub fn evaluate<E>(&self, states: Vec<T>, evaluator: &E) -> Vec<Option<X>>
+    where
+        E: Evaluator<T, Estimate = X>,
+    {
+        let mut pending = self.pending.lock().unwrap();
+        let first_ticket = pending.next_ticket;
+        let tickets = first_ticket..first_ticket + states.len();
+        pending.next_ticket = tickets.end;
+        pending.states.extend(tickets.clone().zip(states));
+
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            if tickets.clone().all(|t| pending.results.contains_key(&t)) {
+                return tickets
+                    .map(|t| pending.results.remove(&t).unwrap())
+                    .collect();
+            }
+
+            let now = Instant::now();
+            let mine_pending = pending.states.iter().any(|(t, _)| tickets.contains(t));
+            if mine_pending && (pending.states.len() >= self.batch_size || now >= deadline) {
+                let (batch_tickets, batch): (Vec<_>, Vec<_>) = pending.states.drain(..).unzip();
+                drop(pending);
+
+                let estimates = evaluator.evaluate_batch(&batch);
+
+                pending = self.pending.lock().unwrap();
+                pending
+                    .results
+                    .extend(batch_tickets.into_iter().zip(estimates));
+                self.ready.notify_all();
+            } else if mine_pending {
+                let wait = deadline.saturating_duration_since(now);
+                pending = self.ready.wait_timeout(pending, wait).unwrap().0;
+            } else {
+                pending = self.ready.wait(pending).unwrap();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::BatchQueue;
+    use crate::{evaluator::Evaluator, testing::Nim};
+
+    /// Estimates a state with its total and records the size of every batch.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        batches: Arc<Mutex<Vec<usize>>>,
+    }
+
+    impl Evaluator<Nim> for Recorder {
+        type Estimate = u32;
+
+        fn evaluate(&self, state: &Nim, _depth: usize) -> Option<u32> {
+            Some(state.total)
+        }
+
+        fn evaluate_batch(&self, states: &[Nim]) -> Vec<Option<u32>> {
+            self.batches.lock().unwrap().push(states.len());
+            states.iter().map(|s| self.evaluate(s, 0)).collect()
+        }
+
+        fn estimate_value(&self, _state: &Nim, _estimate: &u32) -> f64 {
+            0.5
+        }
+    }
+
+    fn states(totals: impl Iterator<Item = u32>) -> Vec<Nim> {
+        totals
+            .map(|total| Nim {
+                total,
+                ..Nim::new(1000)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn results_go_back_to_the_thread_that_queued_them() {
+        let queue = Arc::new(BatchQueue::new(8, Duration::from_millis(5)));
+        let evaluator = Recorder::default();
+        let threads = (0..4)
+            .map(|i| {
+                let queue = queue.clone();
+                let evaluator = evaluator.clone();
+                thread::spawn(move || {
+                    for round in 0..50 {
+                        let totals = (0..2).map(|j| i * 1000 + round * 10 + j);
+                        let estimates = queue.evaluate(states(totals.clone()), &evaluator);
+                        assert_eq!(estimates, totals.map(Some).collect::<Vec<_>>());
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        let batches = evaluator.batches.lock().unwrap();
+        assert_eq!(batches.iter().sum::<usize>(), 4 * 50 * 2);
+        assert!(batches.iter().all(|&size| size <= 8));
+    }
+
+    #[test]
+    fn empty_batch_returns_at_once() {
+        let queue = BatchQueue::new(8, Duration::from_secs(60));
+        let evaluator = Recorder::default();
+        let start = Instant::now();
+        assert!(queue.evaluate(Vec::new(), &evaluator).is_empty());
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(evaluator.batches.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn partial_batch_is_evaluated_after_the_timeout() {
+        let timeout = Duration::from_millis(20);
+        let queue = BatchQueue::new(8, timeout);
+        let evaluator = Recorder::default();
+        let start = Instant::now();
+        let estimates = queue.evaluate(states([3, 5].into_iter()), &evaluator);
+        assert!(start.elapsed() >= timeout);
+        assert_eq!(estimates, vec![Some(3), Some(5)]);
+        assert_eq!(*evaluator.batches.lock().unwrap(), vec![2]);
+    }
+}
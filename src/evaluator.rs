@@ -0,0 +1,112 @@
+use std::convert::Infallible;
+
+use crate::{
+    rng::{self, Rng},
+    GameState,
+};
+
+/// Implement this to score leaves with a heuristic or a learned model instead of (or on top of)
+/// random playouts.
+pub trait Evaluator<T: GameState>: Clone + Send + 'static {
+    /// Value estimate of a non terminal state.
+    type Estimate: Send;
+
+    /// Estimate `state`, reached after `depth` random moves from the leaf (`depth` is 0 for the
+    /// leaf itself). Returning `None` continues the random playout.
+    fn evaluate(&self, state: &T, depth: usize) -> Option<Self::Estimate>;
+
+    /// Estimate a batch of leaves at once, see `MCTS::batch_size`. Defaults to calling
+    /// `evaluate` on each state with a `depth` of 0.
+    fn evaluate_batch(&self, states: &[T]) -> Vec<Option<Self::Estimate>> {
+        states.iter().map(|s| self.evaluate(s, 0)).collect()
+    }
+
+    /// Given an estimate, how beneficial is it for this state? This is the counterpart of
+    /// `GameState::terminal_reward` for estimates and should use the same scale.
+    fn estimate_value(&self, state: &T, estimate: &Self::Estimate) -> f64;
+
+    /// The reward of every player for an estimate, indexed by player. This is the counterpart
+    /// of `GameState::terminal_rewards` for estimates, `estimate_value` is not called when it
+    /// returns `Some`.
+    fn estimate_rewards(&self, estimate: &Self::Estimate) -> Option<Vec<f64>> {
+        let _ = estimate;
+        None
+    }
+
+    /// Prior probabilities of `moves` in `state`, defaults to `GameState::move_priors`.
+    fn move_priors(&self, state: &T, moves: &[T::Move]) -> Vec<f64> {
+        state.move_priors(moves)
+    }
+
+    /// Prior of `m` alone in `state`, for moves `GameState::all_moves` does not list. Defaults
+    /// to `GameState::move_prior`.
+    fn move_prior(&self, state: &T, m: T::Move) -> f64 {
+        state.move_prior(m)
+    }
+}
+
+/// Scores leaves by playing random moves until a terminal state is reached.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RandomPlayout;
+
+impl<T: GameState> Evaluator<T> for RandomPlayout {
+    type Estimate = Infallible;
+
+    fn evaluate(&self, _state: &T, _depth: usize) -> Option<Self::Estimate> {
+        None
+    }
+
+    fn estimate_value(&self, _state: &T, estimate: &Self::Estimate) -> f64 {
+        match *estimate {}
+    }
+}
+
+/// Plays random moves from `state` until a terminal state is reached or the evaluator returns
+/// an estimate, drawing the outcomes of chance states by probability. With `score`, a terminal
+/// state is returned as its `GameState::terminal_reward` (see `MCTS::single_player`). The moves
+/// played are appended to `moves`.
+pub(crate) fn playout<T, E, R>(
+    mut state: T,
+    evaluator: &E,
+    score: bool,
+    rng: &mut R,
+    moves: &mut Vec<T::Move>,
+) -> Outcome<T::UserData, E::Estimate>
+where
+    T: GameState,
+    E: Evaluator<T>,
+    R: Rng,
+{
+    let mut depth = 0;
+    loop {
+        let reward = state.is_terminal_state();
+        if let Some(r) = reward {
+            if score {
+                return Outcome::Score(state.terminal_reward(&r));
+            }
+            return Outcome::Terminal(r);
+        } else if let Some(estimate) = evaluator.evaluate(&state, depth) {
+            return Outcome::Estimate(estimate);
+        } else {
+            let m = match state.chance_outcomes() {
+                Some(outcomes) => {
+                    outcomes[rng::weighted_index(outcomes.iter().map(|o| o.1), rng)].0
+                }
+                None => state.random_move(rng).unwrap(),
+            };
+            state = state.apply_move_with_rng(m, rng);
+            moves.push(m);
+            depth += 1;
+        }
+    }
+}
+
+/// Result of scoring a leaf.
+pub enum Outcome<U, E> {
+    /// a terminal state was reached
+    Terminal(U),
+    /// the evaluator estimated a non terminal state
+    Estimate(E),
+    /// the score of the terminal state reached, in single-player mode
+    Score(f64),
+}
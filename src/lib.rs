@@ -1,15 +1,32 @@
 use std::{
+    cmp::Reverse,
+    collections::HashMap,
     marker::PhantomData,
     ops::{Index, IndexMut},
-    sync::OnceLock,
-    thread::{self, JoinHandle},
+    sync::{Mutex, OnceLock},
+    thread::JoinHandle,
+    time::Duration,
 };
 
+mod atomic;
+mod batch;
+pub mod evaluator;
+pub mod nested;
+pub mod policy;
+pub mod pomcp;
 pub mod rng;
+mod search;
+pub mod simultaneous;
+#[cfg(test)]
+mod testing;
+use atomic::{Count, Reward};
+use evaluator::{Evaluator, Outcome, RandomPlayout};
+use policy::{SelectionPolicy, Stats, Ucb1};
 use rng::{Rng, RngProvider};
+pub use search::Search;
 
 /// statically declared sqrt(2) default exploration constant
-fn default_exploration_constant() -> f64 {
+pub(crate) fn default_exploration_constant() -> f64 {
     static DEFAULT_EXPLORATION_CONSTANT: OnceLock<f64> = OnceLock::new();
 
     *DEFAULT_EXPLORATION_CONSTANT.get_or_init(|| 2.0_f64.sqrt())
@@ -19,7 +36,9 @@ pub trait GameState: Clone {
     type Move: Clone + Copy + Eq;
     type UserData: Eq;
 
-    /// Returns all moves that can be performed from this state.
+    /// Returns all moves that can be performed from this state. This may be empty for action
+    /// spaces that cannot be listed when `sample_move` and `random_move` are implemented and
+    /// progressive widening is used.
     fn all_moves(&self) -> Vec<Self::Move>;
 
     /// A default implementation for a random move from this state, used in random playout.
@@ -33,145 +52,1318 @@ pub trait GameState: Clone {
         }
     }
 
+    /// Draw a new move to widen the tree with, used by progressive widening. Defaults to
+    /// `random_move`.
+    fn sample_move<R: Rng>(&self, rng: &mut R) -> Option<Self::Move> {
+        self.random_move(rng)
+    }
+
+    /// Prior probabilities of `moves`, in the same order, used by prior aware selection policies
+    /// such as `policy::Puct`. Defaults to a uniform distribution.
+    fn move_priors(&self, moves: &[Self::Move]) -> Vec<f64> {
+        vec![1.0 / moves.len() as f64; moves.len()]
+    }
+
+    /// Prior of `m` alone, for moves `all_moves` does not list, such as the moves sampled with
+    /// progressive widening from an action space that cannot be listed. Defaults to 1.
+    fn move_prior(&self, m: Self::Move) -> f64 {
+        let _ = m;
+        1.0
+    }
+
     /// Modify this state by applying this move.
     fn apply_move(&self, action: Self::Move) -> Self;
 
+    /// Apply a move whose outcome may be random, drawing it with `rng`. The search uses this for
+    /// every move it simulates, defaults to `apply_move`. In the default closed-loop mode a node
+    /// keeps the state drawn when it was created, see `MCTS::open_loop` to draw it again on
+    /// every visit.
+    fn apply_move_with_rng<R: Rng>(&self, action: Self::Move, rng: &mut R) -> Self {
+        let _ = rng;
+        self.apply_move(action)
+    }
+
     /// Determine if this is a terminal state. If so then return metadata about the state.
     fn is_terminal_state(&self) -> Option<Self::UserData>;
 
-    /// Given metadata from a terminal state, is this beneficial for this state?
-    fn terminal_is_win(&self, condition: &Self::UserData) -> bool;
+    /// Given metadata from a terminal state, how beneficial is it for this state? Rewards are
+    /// summed and averaged over the visits of each node, the UCT style selection policies expect
+    /// them in `[0, 1]` (e.g. 1 for a win, 0.5 for a draw and 0 for a loss).
+    fn terminal_reward(&self, condition: &Self::UserData) -> f64;
+
+    /// Index of the player to move in this state, only used by games returning per player
+    /// rewards from `terminal_rewards`.
+    fn current_player(&self) -> usize {
+        0
+    }
+
+    /// Given metadata from a terminal state, the reward of every player indexed by player.
+    /// Returning `Some` plays the game in N-player mode: each node accumulates the reward of the
+    /// player who moved into it (the `current_player` of its parent) and `terminal_reward` is
+    /// not called.
+    fn terminal_rewards(&self, condition: &Self::UserData) -> Option<Vec<f64>> {
+        let _ = condition;
+        None
+    }
+
+    /// A hash identifying this state, used to merge states reached through different move
+    /// orders into a single node. With the default of `None` the search is a plain tree.
+    fn hash(&self) -> Option<u64> {
+        None
+    }
+
+    /// A representative of the states equivalent to this one under the symmetries of the game,
+    /// such as board rotations and reflections. Together with `hash`, states with the same
+    /// canonical hash share a node and equivalent moves of a state are only searched once.
+    /// Defaults to the state itself.
+    fn canonical(&self) -> Self {
+        self.clone()
+    }
+
+    /// The random outcomes of a chance state, such as a dice roll or a card draw, with their
+    /// probabilities. Outcomes are applied with `apply_move` and are sampled by probability
+    /// instead of being chosen by the selection policy, the value of a chance node being the
+    /// expectation over its outcomes. Outcomes should keep the point of view of the chance
+    /// state: the same `current_player` and `terminal_reward` perspective as the player who
+    /// moved into it. Defaults to `None`, a decision state.
+    fn chance_outcomes(&self) -> Option<Vec<(Self::Move, f64)>> {
+        None
+    }
+
+    /// Sample a state consistent with what player `observer` knows of this one, drawing the
+    /// information hidden from them (such as the cards of the other players) with `rng`. Used by
+    /// `MCTS::ismcts`, defaults to the state itself for games of perfect information.
+    fn determinize<R: Rng>(&self, observer: usize, rng: &mut R) -> Self {
+        let _ = (observer, rng);
+        self.clone()
+    }
+
+    /// A key of what the player observes on reaching this state. In observation mode the
+    /// outcomes of chance states are drawn with `apply_move_with_rng` and every observation gets
+    /// its own child, see `Tree::observations`. Defaults to `None`.
+    fn observation(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// The children of the nodes `cursors` of `trees` merged by move, each with the node it leads to
+/// in every tree, the most visited first. Moves are those of `state`, the state reached along
+/// the line, as the nodes may have been reached first in another orientation.
+fn merged_children<T, P, E>(
+    trees: &[&Tree<T, P, E>],
+    cursors: &[Option<usize>],
+    state: &T,
+) -> Vec<(RootChild<T>, Vec<Option<usize>>)>
+where
+    T: GameState,
+    P: SelectionPolicy,
+    E: Evaluator<T>,
+{
+    let mut children: Vec<(RootChild<T>, Vec<Option<usize>>)> = Vec::new();
+    for (i, (tree, cursor)) in trees.iter().zip(cursors).enumerate() {
+        let Some(idx) = *cursor else {
+            continue;
+        };
+        let moves = tree.reoriented_moves(idx, state);
+        for ((_, child, stats), mv) in tree.edges(idx).zip(moves) {
+            let Some(mv) = mv else {
+                continue;
+            };
+            let pos = match children.iter().position(|c| c.0.mv == mv) {
+                Some(pos) => pos,
+                None => {
+                    let child = RootChild {
+                        mv,
+                        visits: 0,
+                        reward: 0.0,
+                        reward_sq: 0.0,
+                        proof: None,
+                    };
+                    children.push((child, vec![None; trees.len()]));
+                    children.len() - 1
+                }
+            };
+            let (merged, next) = &mut children[pos];
+            merged.visits += stats.visits;
+            merged.reward += stats.reward;
+            merged.reward_sq += stats.reward_sq;
+            merged.proof = merged.proof.or(tree[child].proof);
+            next[i] = Some(child);
+        }
+    }
+    children.sort_by_key(|c| Reverse(c.0.visits));
+    children
+}
+
+/// The most visited line of up to `depth` moves from `state`, the state of the nodes `cursors` of
+/// `trees`.
+fn principal_variation<T, P, E>(
+    trees: &[&Tree<T, P, E>],
+    mut cursors: Vec<Option<usize>>,
+    mut state: T,
+    depth: usize,
+) -> Vec<T::Move>
+where
+    T: GameState,
+    P: SelectionPolicy,
+    E: Evaluator<T>,
+{
+    let mut line = Vec::new();
+    while line.len() < depth {
+        let Some((best, next)) = merged_children(trees, &cursors, &state).into_iter().next() else {
+            break;
+        };
+        if best.visits == 0 {
+            break;
+        }
+        // open-loop nodes have no state of their own to follow
+        if !trees[0].open_loop {
+            state = state.apply_move(best.mv);
+        }
+        line.push(best.mv);
+        cursors = next;
+    }
+    line
+}
+
+/// The `k` most visited root moves of `trees` with their principal variation, moves that were
+/// never visited being left out.
+fn multi_pv<T, P, E>(trees: &[&Tree<T, P, E>], k: usize, depth: usize) -> Vec<Line<T::Move>>
+where
+    T: GameState,
+    P: SelectionPolicy,
+    E: Evaluator<T>,
+{
+    let root = vec![Some(0); trees.len()];
+    let state = &trees[0][0].state;
+    merged_children(trees, &root, state)
+        .into_iter()
+        .filter(|(child, _)| child.visits > 0)
+        .take(k)
+        .map(|(child, next)| {
+            let next_state = if trees[0].open_loop {
+                state.clone()
+            } else {
+                state.apply_move(child.mv)
+            };
+            let mut moves = vec![child.mv];
+            moves.extend(principal_variation(
+                trees,
+                next,
+                next_state,
+                depth.saturating_sub(1),
+            ));
+            Line {
+                stats: child.stats(),
+                moves,
+            }
+        })
+        .collect()
+}
+
+/// Key of the node of `state` among the transpositions, see `GameState::canonical`.
+fn transposition_key<T: GameState>(state: &T) -> Option<u64> {
+    state.hash()?;
+    state.canonical().hash()
+}
+
+/// How children are created when a node is expanded.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Expansion {
+    /// Create a child for every move at once.
+    #[default]
+    Full,
+    /// Create one child per visit, trying the untried moves in random order.
+    Lazy,
+    /// Create one child per visit, trying the untried moves by decreasing prior (see
+    /// `Evaluator::move_priors`).
+    Ordered,
+    /// Allow `ceil(k * n^alpha)` children for a node visited `n` times, new moves being drawn
+    /// with `GameState::sample_move`. This does not need `GameState::all_moves`.
+    ProgressiveWidening { k: f64, alpha: f64 },
+}
+
+/// Proven outcome of a node, from the point of view of its state (the same point of view as
+/// `GameState::terminal_reward`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Proof {
+    Win,
+    Loss,
+    Draw,
+}
+
+impl Proof {
+    /// Proof of a terminal `state` reached from `parent`: a win if the reward of `state` is
+    /// higher than the reward of `parent`, a loss if it is lower and a draw otherwise. In
+    /// N-player mode the reward of the player who moved into `state` is compared to the best
+    /// reward of the other players.
+    fn of_terminal<T: GameState>(state: &T, parent: &T, condition: &T::UserData) -> Self {
+        let (reward, other_reward) = match state.terminal_rewards(condition) {
+            Some(rewards) => {
+                let mover = parent.current_player();
+                let other_reward = rewards
+                    .iter()
+                    .enumerate()
+                    .filter(|&(player, _)| player != mover)
+                    .fold(f64::NEG_INFINITY, |best, (_, &r)| best.max(r));
+                (rewards[mover], other_reward)
+            }
+            None => (
+                state.terminal_reward(condition),
+                parent.terminal_reward(condition),
+            ),
+        };
+        match reward.total_cmp(&other_reward) {
+            std::cmp::Ordering::Greater => Proof::Win,
+            std::cmp::Ordering::Less => Proof::Loss,
+            std::cmp::Ordering::Equal => Proof::Draw,
+        }
+    }
+
+    /// The same outcome from the point of view of the opponent.
+    fn reverse(self) -> Self {
+        match self {
+            Proof::Win => Proof::Loss,
+            Proof::Loss => Proof::Win,
+            Proof::Draw => Proof::Draw,
+        }
+    }
+}
+
+/// How search threads share the work.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Parallelism {
+    /// Each thread searches its own tree, the visits of the root children are summed at the end.
+    #[default]
+    Root,
+    /// All threads search a single tree and are spread out with virtual loss. The statistics of
+    /// the nodes and the virtual losses are atomics: threads select and backpropagate at the same
+    /// time, and only lock the whole tree to add nodes. Open-loop trees, whose states are
+    /// simulated again while selecting, stay locked while selecting too.
+    Tree,
+    /// A single thread searches the tree and every new leaf is played out `num_threads` times in
+    /// parallel by a pool of worker threads, all the results being backpropagated together.
+    Leaf,
+}
+
+/// Link from a node to one of its children. With transpositions a node can be the child of
+/// several parents, each edge keeping its own move, prior and visit count.
+struct Edge<M> {
+    child: usize,
+    mv: M,
+    prior: f64,
+    /// visits of the child through this edge
+    visits: Count,
+    /// iterations in which the move was legal when the parent was visited, only counted for
+    /// ISMCTS
+    available: Count,
 }
 
 pub struct Node<T>
 where
     T: GameState,
 {
-    n: u32,
-    w: u32,
+    n: Count,
+    w: Reward,
+    /// sum of the squared rewards
+    w2: Reward,
+    /// visits and rewards of the results this node was the leaf of
+    leaf_n: Count,
+    leaf_w: Reward,
+    /// sum of the rewards of those results from the opposite point of view
+    leaf_opponent_w: Reward,
+    /// value recomputed from the children, see `Tree::backpropagate`
+    q: Reward,
+    /// the same value from the opposite point of view, that of the parents of the children
+    opponent_q: Reward,
+    /// pending visits of threads or descents that have not backpropagated yet
+    vl: Count,
+    /// all-moves-as-first visits and rewards, only gathered for policies using RAVE
+    amaf_n: Count,
+    amaf_w: Reward,
+    /// moves, with their priors, that do not have a child yet. `None` until the node is expanded
+    untried: Option<Vec<(T::Move, f64)>>,
+    /// priors of the moves listed by `GameState::all_moves`, for the expansion modes that create
+    /// children for sampled moves. `None` until they are first needed, see `Tree::prior`
+    priors: Option<Vec<(T::Move, f64)>>,
+    /// set once the solver proved the outcome of this node
+    proof: Option<Proof>,
+    /// whether the state is a chance state, see `GameState::chance_outcomes`
+    chance: bool,
+    /// In open-loop mode, the state of the latest simulation through this node.
     pub state: T,
-    children: Vec<usize>,
-    parent: Option<usize>,
+    /// In observation mode, states simulated through this node kept as its belief by
+    /// `pomcp::Pomcp`.
+    belief: Vec<T>,
+    children: Vec<Edge<T::Move>>,
+    /// every node this node is a child of, the root has none
+    parents: Vec<usize>,
 }
 
 impl<T> Node<T>
 where
     T: GameState,
 {
-    pub fn new(t: T, parent: Option<usize>) -> Self {
+    pub fn new(t: T) -> Self {
         Self {
-            n: 1,
-            w: 0,
+            n: Count::new(1),
+            w: Reward::default(),
+            w2: Reward::default(),
+            leaf_n: Count::default(),
+            leaf_w: Reward::default(),
+            leaf_opponent_w: Reward::default(),
+            q: Reward::default(),
+            opponent_q: Reward::default(),
+            vl: Count::default(),
+            amaf_n: Count::default(),
+            amaf_w: Reward::default(),
+            untried: None,
+            priors: None,
+            proof: None,
+            chance: t.chance_outcomes().is_some(),
             state: t,
+            belief: Vec::new(),
             children: Vec::new(),
-            parent,
+            parents: Vec::new(),
+        }
+    }
+
+    /// The outcome of this node, if the solver proved it.
+    pub fn proof(&self) -> Option<Proof> {
+        self.proof
+    }
+
+    /// Whether the outcome of this node is drawn at random rather than chosen by a player.
+    pub fn is_chance(&self) -> bool {
+        self.chance
+    }
+
+    /// Whether every move of this node has a child.
+    pub fn is_fully_expanded(&self) -> bool {
+        self.untried.as_ref().is_some_and(|u| u.is_empty())
+    }
+
+    /// Statistics of this node over every path through it. The prior is 1, priors belong to
+    /// the edges from the parents (see `Tree::child_stats`).
+    pub fn stats(&self) -> Stats {
+        Stats {
+            visits: self.n.get() + self.vl.get(),
+            reward: self.w.get(),
+            reward_sq: self.w2.get(),
+            prior: 1.0,
+            amaf_visits: self.amaf_n.get(),
+            amaf_reward: self.amaf_w.get(),
+            available: None,
         }
     }
 }
 
-pub struct Tree<T: GameState> {
+pub struct Tree<T: GameState, P: SelectionPolicy = Ucb1, E: Evaluator<T> = RandomPlayout> {
     nodes: Vec<Node<T>>,
-    exploration_factor: f64,
+    policy: P,
+    evaluator: E,
+    expansion: Expansion,
+    solver: bool,
+    open_loop: bool,
+    /// the root state as known by the player to move, determinized on every iteration of
+    /// ISMCTS
+    information_set: Option<T>,
+    ismcts: bool,
+    observations: bool,
+    single_player: bool,
+    /// best complete trajectory from the root, in single-player mode
+    best: Mutex<Option<Trajectory<T::Move>>>,
+    /// node of every state with a `GameState::hash`
+    transpositions: HashMap<u64, usize>,
 }
 
-impl<T: GameState> Tree<T> {
-    pub fn new(exploration_factor: f64) -> Self {
+impl<T: GameState, P: SelectionPolicy, E: Evaluator<T>> Tree<T, P, E> {
+    pub fn new(policy: P, evaluator: E) -> Self {
         Self {
             nodes: Vec::new(),
-            exploration_factor,
+            policy,
+            evaluator,
+            expansion: Expansion::Full,
+            solver: false,
+            open_loop: false,
+            information_set: None,
+            ismcts: false,
+            observations: false,
+            single_player: false,
+            best: Mutex::new(None),
+            transpositions: HashMap::new(),
         }
     }
 
-    pub fn add_node_with_parent(&mut self, n: Node<T>) -> usize {
-        let parent = n.parent;
-        let len = self.nodes.len();
-        self.nodes.push(n);
-        if let Some(parent) = parent {
-            self.nodes.get_mut(parent).unwrap().children.push(len);
+    pub fn expansion(mut self, expansion: Expansion) -> Self {
+        self.expansion = expansion;
+        self
+    }
+
+    /// Enable MCTS-Solver, see `MCTS::solver`.
+    pub fn solver(mut self, solver: bool) -> Self {
+        self.solver = solver;
+        self
+    }
+
+    /// Simulate the moves again on every visit, see `MCTS::open_loop`.
+    pub fn open_loop(mut self, open_loop: bool) -> Self {
+        self.open_loop = open_loop;
+        self
+    }
+
+    /// Search the information sets of the player to move at the root, see `MCTS::ismcts`. This
+    /// implies open-loop mode.
+    pub fn ismcts(mut self, ismcts: bool) -> Self {
+        self.ismcts = ismcts;
+        self.open_loop |= ismcts;
+        self
+    }
+
+    /// Draw the outcomes of chance nodes with `GameState::apply_move_with_rng` instead of
+    /// listing them, each `GameState::observation` getting its own child, as the histories of
+    /// POMCP (see `pomcp::Pomcp`). Nodes are valued by the mean of the results backpropagated
+    /// through them from their own point of view, outcomes of chance nodes being free to have
+    /// another. This implies open-loop mode.
+    pub fn observations(mut self, observations: bool) -> Self {
+        self.observations = observations;
+        self.open_loop |= observations;
+        self
+    }
+
+    /// Keep track of the best complete trajectory, see `MCTS::single_player`.
+    pub fn single_player(mut self, single_player: bool) -> Self {
+        self.single_player = single_player;
+        self
+    }
+
+    /// The best complete trajectory from the root found so far, in single-player mode.
+    pub fn best_trajectory(&self) -> Option<Trajectory<T::Move>> {
+        self.best.lock().unwrap().clone()
+    }
+
+    /// Whether the solver proved the outcome of the root.
+    pub fn is_solved(&self) -> bool {
+        self.nodes[0].proof.is_some()
+    }
+
+    /// Add a node without any parent, the first node added is the root.
+    pub fn add_node(&mut self, n: Node<T>) -> usize {
+        if self.ismcts && self.nodes.is_empty() {
+            self.information_set = Some(n.state.clone());
         }
-        len
+        if let Some(key) = transposition_key(&n.state).filter(|_| !self.open_loop) {
+            self.transpositions.insert(key, self.nodes.len());
+        }
+        self.nodes.push(n);
+        self.nodes.len() - 1
     }
 
-    /// upper confidence bound calculation
-    fn uct(&self, node_idx: usize, parent_idx: usize) -> f64 {
-        let node = &self.nodes[node_idx];
-        let parent = &self.nodes[parent_idx];
+    /// Make `child` a child of `parent`, reached with move `m`.
+    fn link(&mut self, parent: usize, child: usize, m: T::Move, prior: f64) {
+        self.nodes[parent].children.push(Edge {
+            child,
+            mv: m,
+            prior,
+            visits: Count::new(1),
+            available: Count::default(),
+        });
+        self.nodes[child].parents.push(parent);
+    }
 
-        let win_prob = node.w as f64 / node.n as f64;
-        let exploration = self.exploration_factor * ((parent.n as f64).ln() / node.n as f64).sqrt();
+    /// Statistics of the `i`th child of `idx` as seen by a selection policy: the value of the
+    /// child, shared by all its parents (see `backpropagate`), counted once per visit through
+    /// this edge. The rewards of a chance node are its expected value.
+    pub fn child_stats(&self, idx: usize, i: usize) -> Stats {
+        let edge = &self[idx].children[i];
+        let child = &self[edge.child];
+        let visits = edge.visits.get();
+        let scale = visits as f64 / child.n.get() as f64;
+        let reward = match self.expected_value(edge.child, |c| c.q.get()) {
+            Some(value) => value * visits as f64,
+            None => child.q.get() * (visits - 1) as f64,
+        };
+        Stats {
+            visits: visits + child.vl.get(),
+            reward,
+            reward_sq: child.w2.get() * scale,
+            prior: edge.prior,
+            amaf_visits: child.amaf_n.get(),
+            amaf_reward: child.amaf_w.get(),
+            available: self.ismcts.then(|| edge.available.get()),
+        }
+    }
 
-        win_prob + exploration
+    /// Traverse fully expanded nodes from the root, descending into the child picked by the
+    /// selection policy, and return the path taken. In open-loop mode the states along the path
+    /// are simulated again, from a new determinization of the root for ISMCTS.
+    pub fn select<R: Rng>(&mut self, rng: &mut R) -> Vec<usize> {
+        if let Some(information_set) = &self.information_set {
+            let observer = information_set.current_player();
+            self.nodes[0].state = information_set.determinize(observer, rng);
+        }
+        let mut path = vec![0];
+        self.select_from(&mut path, rng);
+        path
     }
 
-    /// Traverse children and find node with bets UCT.
-    pub fn select(&self) -> usize {
-        let mut nidx = 0;
+    /// Same as `select`, extending `path` from its last node instead of starting at the root.
+    /// Selection also stops before a child that is already on the path.
+    /// In observation mode, selection stops at the child added for a new observation.
+    pub fn select_from<R: Rng>(&mut self, path: &mut Vec<usize>, rng: &mut R) {
         loop {
-            let p = &self[nidx];
-            if p.state.is_terminal_state().is_some() {
-                return nidx;
+            let idx = *path.last().unwrap();
+            if self.observations && self[idx].chance {
+                let (child, new) = self.observe(idx, rng);
+                path.push(child);
+                if new {
+                    return;
+                }
+                continue;
+            }
+            let Some(child) = self.next_child(path, rng) else {
+                return;
+            };
+            self.resimulate(idx, child, rng);
+            path.push(child);
+        }
+    }
+
+    /// In observation mode, draw an outcome of chance node `idx` and return the child of the
+    /// observation made, which gets the state drawn, and whether that child was added for a new
+    /// observation.
+    fn observe<R: Rng>(&mut self, idx: usize, rng: &mut R) -> (usize, bool) {
+        let node = &self[idx];
+        let outcomes = node.state.chance_outcomes().unwrap_or_default();
+        let (m, probability) = outcomes[rng::weighted_index(outcomes.iter().map(|o| o.1), rng)];
+        let state = node.state.apply_move_with_rng(m, rng);
+        let observation = state.observation();
+        let existing = node
+            .children
+            .iter()
+            .find(|e| e.mv == m && self[e.child].state.observation() == observation);
+        match existing.map(|e| e.child) {
+            Some(child) => {
+                self[child].state = state;
+                (child, false)
+            }
+            None => {
+                let child = self.add_node(Node::new(state));
+                self.link(idx, child, m, probability);
+                (child, true)
+            }
+        }
+    }
+
+    /// Same as `select` for threads sharing the tree, adding a virtual loss on every node of the
+    /// path as it is descended so that the descents of the other threads are spread out at once.
+    /// Only for closed-loop trees, whose states are not simulated again.
+    pub(crate) fn select_shared<R: Rng>(&self, rng: &mut R) -> Vec<usize> {
+        debug_assert!(!self.open_loop);
+        let mut path = vec![0];
+        self.add_virtual_loss(&path);
+        while let Some(child) = self.next_child(&path, rng) {
+            self.add_virtual_loss(&[child]);
+            path.push(child);
+        }
+        path
+    }
+
+    /// The child to descend into from the last node of `path`, `None` if selection stops there.
+    fn next_child<R: Rng>(&self, path: &[usize], rng: &mut R) -> Option<usize> {
+        let nidx = *path.last().unwrap();
+        let p = &self[nidx];
+        if p.state.is_terminal_state().is_some()
+            || self.needs_expansion(nidx)
+            || p.children.is_empty()
+        {
+            return None;
+        }
+        self.count_available(nidx);
+        let child = self.choose_child(nidx, rng);
+        (!path.contains(&child)).then_some(child)
+    }
+
+    /// In open-loop mode, draw the state of `child` again from the state of its parent `idx`.
+    pub fn resimulate<R: Rng>(&mut self, idx: usize, child: usize, rng: &mut R) {
+        if !self.open_loop {
+            return;
+        }
+        let m = self[idx]
+            .children
+            .iter()
+            .find(|e| e.child == child)
+            .unwrap()
+            .mv;
+        self[child].state = self[idx].state.apply_move_with_rng(m, rng);
+    }
+
+    /// Probability weighted mean of the `value` of the visited outcomes of chance node `idx`,
+    /// `None` for decision nodes, chance nodes without visited outcomes and in observation mode.
+    fn expected_value(&self, idx: usize, value: impl Fn(&Node<T>) -> f64) -> Option<f64> {
+        let node = &self[idx];
+        if !node.chance || self.observations {
+            return None;
+        }
+        let (value, probability) = node
+            .children
+            .iter()
+            .filter(|e| self[e.child].n.get() > 1)
+            .fold((0.0, 0.0), |(sum, probability), e| {
+                (sum + e.prior * value(&self[e.child]), probability + e.prior)
+            });
+        (probability > 0.0).then(|| value / probability)
+    }
+
+    /// For ISMCTS, count an iteration in which the children of `idx` whose move is legal in its
+    /// current determinization were available.
+    fn count_available(&self, idx: usize) {
+        if !self.ismcts {
+            return;
+        }
+        let moves = self[idx].state.all_moves();
+        for e in &self[idx].children {
+            if moves.contains(&e.mv) {
+                e.available.add(1);
+            }
+        }
+    }
+
+    /// Legal moves of the current determinization of `idx` that do not have a child yet.
+    fn untried_moves(&self, idx: usize) -> Vec<T::Move> {
+        let node = &self[idx];
+        let mut moves = node.state.all_moves();
+        moves.retain(|&m| !node.children.iter().any(|e| e.mv == m));
+        moves
+    }
+
+    /// The child of `idx` picked by the selection policy. With the solver enabled, a proven win
+    /// is picked right away and other proven children are skipped unless every child is proven.
+    /// The outcome of a chance node is drawn by probability instead. For ISMCTS, only children
+    /// whose move is legal in the current determinization are considered and the visits of the
+    /// parent are replaced by the availability of each child (see `Stats::available`).
+    pub fn choose_child<R: Rng>(&self, idx: usize, rng: &mut R) -> usize {
+        let p = &self[idx];
+        if p.chance {
+            let i = rng::weighted_index(p.children.iter().map(|e| e.prior), rng);
+            return p.children[i].child;
+        }
+        // a proven win is always played, proven nodes are still descended into below chance nodes
+        let win = p
+            .children
+            .iter()
+            .find(|e| self[e.child].proof == Some(Proof::Win));
+        if let Some(e) = win.filter(|_| self.solver) {
+            return e.child;
+        }
+        let mut candidates = (0..p.children.len()).collect::<Vec<_>>();
+        let unproven = |&i: &usize| self[p.children[i].child].proof.is_none();
+        if self.solver && candidates.iter().any(unproven) {
+            candidates.retain(unproven);
+        }
+        if self.ismcts {
+            let moves = p.state.all_moves();
+            candidates.retain(|&i| moves.contains(&p.children[i].mv));
+        }
+        let children = candidates
+            .iter()
+            .map(|&i| self.child_stats(idx, i))
+            .collect::<Vec<_>>();
+        let choice = self.policy.choose(&p.stats(), &children, rng);
+        p.children[candidates[choice]].child
+    }
+
+    /// Whether the node should be expanded instead of descended into.
+    fn needs_expansion(&self, idx: usize) -> bool {
+        let node = &self[idx];
+        if self.ismcts && !node.chance {
+            return !self.untried_moves(idx).is_empty();
+        }
+        match self.expansion {
+            Expansion::ProgressiveWidening { k, alpha } if !node.chance => {
+                (node.children.len() as f64) < (k * (node.n.get() as f64).powf(alpha)).ceil()
             }
-            if p.children.is_empty() {
-                break;
+            _ => !node.is_fully_expanded(),
+        }
+    }
+
+    /// Creates children for a given node index and returns their indexes. Depending on the
+    /// expansion mode this is every child at once or a single new child per call. With
+    /// progressive widening, nothing is returned if the sampled move already has a child.
+    /// Children may be existing nodes when their state was already reached by another path, moves
+    /// leading to a node that already is a child of `idx` are skipped. Chance nodes get a child
+    /// for every outcome at once, or in observation mode the child of a single outcome drawn at
+    /// random (see `observations`). For ISMCTS, a single child is created for a random legal move
+    /// of the current determinization.
+    pub fn expand<R: Rng>(&mut self, idx: usize, rng: &mut R) -> Vec<usize> {
+        let state = self[idx].state.clone();
+        if self[idx].chance && self.observations {
+            return vec![self.observe(idx, rng).0];
+        }
+        if self[idx].chance {
+            if self[idx].untried.is_some() {
+                return Vec::new();
+            }
+            self[idx].untried = Some(Vec::new());
+            let children = state
+                .chance_outcomes()
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|(m, probability)| self.add_child(idx, m, probability, rng))
+                .collect();
+            self.propagate_proof(idx);
+            return children;
+        }
+
+        if self.ismcts {
+            let moves = self.untried_moves(idx);
+            let child = (!moves.is_empty()).then(|| {
+                let m = moves[rng.gen_range(0..moves.len())];
+                let prior = self.prior(idx, m);
+                self.add_child(idx, m, prior, rng)
+            });
+            self.count_available(idx);
+            return child.flatten().into_iter().collect();
+        }
+
+        if let Expansion::ProgressiveWidening { .. } = self.expansion {
+            let Some(m) = state.sample_move(rng) else {
+                return Vec::new();
+            };
+            if self[idx].children.iter().any(|e| e.mv == m) {
+                return Vec::new();
+            }
+            let prior = self.prior(idx, m);
+            let Some(child) = self.add_child(idx, m, prior, rng) else {
+                return Vec::new();
+            };
+            self.propagate_proof(idx);
+            return vec![child];
+        }
+
+        if self[idx].untried.is_none() {
+            let moves = state.all_moves();
+            let priors = self.evaluator.move_priors(&state, &moves);
+            let mut untried = moves.into_iter().zip(priors).collect::<Vec<_>>();
+            if self.expansion == Expansion::Ordered {
+                // best move last, it is popped first
+                untried.sort_by(|a, b| a.1.total_cmp(&b.1));
+            }
+            self[idx].untried = Some(untried);
+        }
+
+        let expansion = self.expansion;
+        let untried = self[idx].untried.as_mut().unwrap();
+        let expanded = match expansion {
+            Expansion::Full => std::mem::take(untried),
+            Expansion::Lazy if !untried.is_empty() => {
+                vec![untried.swap_remove(rng.gen_range(0..untried.len()))]
+            }
+            Expansion::Ordered => untried.pop().into_iter().collect(),
+            Expansion::Lazy | Expansion::ProgressiveWidening { .. } => Vec::new(),
+        };
+
+        let children = expanded
+            .into_iter()
+            .filter_map(|(m, prior)| self.add_child(idx, m, prior, rng))
+            .collect();
+        self.propagate_proof(idx);
+        children
+    }
+
+    /// Prior of move `m` of `idx`. The priors of all the moves of the node are computed at once
+    /// the first time and kept, a move that `GameState::all_moves` does not list gets
+    /// `Evaluator::move_prior` instead. For ISMCTS the moves are those of the determinization the
+    /// node is first expanded in.
+    fn prior(&mut self, idx: usize, m: T::Move) -> f64 {
+        let node = &self.nodes[idx];
+        if node.priors.is_none() {
+            let moves = node.state.all_moves();
+            let priors = self.evaluator.move_priors(&node.state, &moves);
+            self.nodes[idx].priors = Some(moves.into_iter().zip(priors).collect());
+        }
+        let node = &self.nodes[idx];
+        match node.priors.as_ref().unwrap().iter().find(|p| p.0 == m) {
+            Some(&(_, prior)) => prior,
+            None => self.evaluator.move_prior(&node.state, m),
+        }
+    }
+
+    /// Create the child of `idx` reached with move `m`, proving it when it is terminal and the
+    /// solver is enabled. A state with the same `GameState::hash` as an existing node is merged
+    /// with it instead, or skipped if that node already is a child of `idx` (outcomes of a
+    /// chance node adding up their probabilities).
+    fn add_child<R: Rng>(
+        &mut self,
+        idx: usize,
+        m: T::Move,
+        prior: f64,
+        rng: &mut R,
+    ) -> Option<usize> {
+        let state = self[idx].state.apply_move_with_rng(m, rng);
+        let existing = transposition_key(&state)
+            .filter(|_| !self.open_loop)
+            .and_then(|key| self.transpositions.get(&key).copied());
+        if let Some(child) = existing {
+            let chance = self[idx].chance;
+            if let Some(e) = self[idx].children.iter_mut().find(|e| e.child == child) {
+                if chance {
+                    e.prior += prior;
+                }
+                return None;
+            }
+        }
+        let child = match existing {
+            Some(child) => child,
+            None => {
+                let mut n = Node::new(state);
+                if let Some(opponent) = self.solver.then(|| self.opponent(idx)).flatten() {
+                    if let Some(condition) = n.state.is_terminal_state() {
+                        let opponent = &self[opponent].state;
+                        n.proof = Some(Proof::of_terminal(&n.state, opponent, &condition));
+                    }
+                }
+                self.add_node(n)
+            }
+        };
+        self.link(idx, child, m, prior);
+        Some(child)
+    }
+
+    /// The node whose point of view is the opposite of the children of `idx`: `idx` itself, or
+    /// its closest decision ancestor for chance nodes.
+    fn opponent(&self, idx: usize) -> Option<usize> {
+        let mut node = Some(idx);
+        while let Some(idx) = node.filter(|&idx| self[idx].chance) {
+            node = self[idx].parents.first().copied();
+        }
+        node
+    }
+
+    /// Prove `idx` and its ancestors from the proofs of their children with minimax rules. This
+    /// assumes a two player, zero sum game where players alternate: a node is lost if any child
+    /// is won, won if every child is lost, and a draw if every child is proven otherwise. A
+    /// chance node is only proven when all its outcomes have the same proof.
+    fn propagate_proof(&mut self, idx: usize) {
+        if !self.solver {
+            return;
+        }
+        let mut pending = vec![idx];
+        while let Some(idx) = pending.pop() {
+            let n = &self[idx];
+            if n.proof.is_some() || n.children.is_empty() {
+                continue;
+            }
+            let proofs = n.children.iter().map(|e| self[e.child].proof);
+            let proof = if n.chance {
+                match proofs.clone().next().flatten() {
+                    Some(proof) if proofs.clone().all(|p| p == Some(proof)) => proof,
+                    _ => continue,
+                }
+            } else if proofs.clone().any(|p| p == Some(Proof::Win)) {
+                Proof::Loss
+            } else if !n.is_fully_expanded() || proofs.clone().any(|p| p.is_none()) {
+                continue;
+            } else if proofs.clone().all(|p| p == Some(Proof::Loss)) {
+                Proof::Win
             } else {
-                let best_uct_opt = p
+                Proof::Draw
+            };
+            pending.extend_from_slice(&n.parents);
+            self[idx].proof = Some(proof);
+        }
+    }
+
+    /// Plays random moves from node `n` until a terminal state is reached or the evaluator
+    /// returns an estimate. In single-player mode a terminal state is returned as its score. The
+    /// moves played are appended to `moves`.
+    pub fn random_playout<R: Rng>(
+        &self,
+        n: usize,
+        rng: &mut R,
+        moves: &mut Vec<T::Move>,
+    ) -> Outcome<T::UserData, E::Estimate> {
+        let state = self[n].state.clone();
+        evaluator::playout(state, &self.evaluator, self.single_player, rng, moves)
+    }
+
+    /// Count a pending visit, without reward, on the nodes of `path` so that other descents are
+    /// steered away from it until its result is backpropagated.
+    pub fn add_virtual_loss(&self, path: &[usize]) {
+        for &idx in path {
+            self.nodes[idx].vl.add(1);
+        }
+    }
+
+    /// Undo `add_virtual_loss`.
+    pub fn remove_virtual_loss(&self, path: &[usize]) {
+        for &idx in path {
+            self.nodes[idx].vl.sub(1);
+        }
+    }
+
+    /// Rewards of every player for a result reached from `idx`, in N-player mode.
+    fn player_rewards(
+        &self,
+        idx: usize,
+        result: &Outcome<T::UserData, E::Estimate>,
+    ) -> Option<Vec<f64>> {
+        match result {
+            Outcome::Terminal(condition) => self.nodes[idx].state.terminal_rewards(condition),
+            Outcome::Estimate(estimate) => self.evaluator.estimate_rewards(estimate),
+            Outcome::Score(_) => None,
+        }
+    }
+
+    /// How beneficial a result is for node `idx`, `mover` being the player who moved into it
+    /// (or the player to move for the root).
+    fn value(
+        &self,
+        idx: usize,
+        mover: usize,
+        result: &Outcome<T::UserData, E::Estimate>,
+        rewards: Option<&[f64]>,
+    ) -> f64 {
+        let state = &self.nodes[idx].state;
+        match (rewards, result) {
+            (_, Outcome::Score(score)) => *score,
+            (Some(rewards), _) => rewards[mover],
+            (None, Outcome::Terminal(condition)) => state.terminal_reward(condition),
+            (None, Outcome::Estimate(estimate)) => self.evaluator.estimate_value(state, estimate),
+        }
+    }
+
+    /// Backpropagate a result along `path`, from the leaf it ends with up to the root, then
+    /// recompute the value of every node of the path from its children as in Monte Carlo Graph
+    /// Search: the mean of the results the node was the leaf of and of the recomputed value of
+    /// each child, weighted by the visits of the edge to that child. Children are valued from the
+    /// point of view of the node, which is the opposite of their own as this assumes two players
+    /// who alternate (outcomes of chance nodes keep their point of view, see
+    /// `GameState::chance_outcomes`), so every node keeps its value from both points of view. In
+    /// a tree this is the mean of the results backpropagated through the node, with
+    /// transpositions the visits of a node through all its parents inform the value of all its
+    /// ancestors. Only the nodes and edges on `path` are updated. `moves` are the moves played
+    /// after the leaf (see `random_playout`), used to update all-moves-as-first statistics when
+    /// the policy uses them. In single-player mode every node gets the value of the leaf, the
+    /// score of a terminal leaf being its `terminal_reward`.
+    pub fn backpropagate(
+        &self,
+        path: &[usize],
+        result: Outcome<T::UserData, E::Estimate>,
+        moves: &[T::Move],
+    ) {
+        let amaf = self.policy.uses_amaf();
+        let mut played = if amaf { moves.to_vec() } else { Vec::new() };
+        let leaf = *path.last().unwrap();
+        let result = match result {
+            Outcome::Terminal(condition) if self.single_player => {
+                Outcome::Score(self.nodes[leaf].state.terminal_reward(&condition))
+            }
+            result => result,
+        };
+        if let (true, Outcome::Score(score)) = (self.single_player, &result) {
+            self.record_trajectory(path, *score, moves);
+        }
+        let rewards = self.player_rewards(leaf, &result);
+        let rewards = rewards.as_deref();
+        let value = |i: usize| {
+            let parent = i.checked_sub(1).map(|i| path[i]);
+            let mover = self.nodes[parent.unwrap_or(path[i])].state.current_player();
+            self.value(path[i], mover, &result, rewards)
+        };
+        let values = if self.single_player {
+            vec![value(path.len() - 1); path.len()]
+        } else {
+            (0..path.len()).map(value).collect::<Vec<_>>()
+        };
+        for (i, &idx) in path.iter().enumerate().rev() {
+            let parent = i.checked_sub(1).map(|i| path[i]);
+            let value = values[i];
+            // the closest decision node above has the opposite point of view, the root has none
+            // and counts its own value
+            let opponent = (0..i).rev().find(|&j| !self.nodes[path[j]].chance);
+            let opponent_value = values[opponent.unwrap_or(i)];
+            let n = &self.nodes[idx];
+            n.n.add(1);
+            n.w.add(value);
+            n.w2.add(value * value);
+            if i + 1 == path.len() {
+                n.leaf_n.add(1);
+                n.leaf_w.add(value);
+                n.leaf_opponent_w.add(opponent_value);
+            }
+
+            if amaf {
+                let player = self.nodes[idx].state.current_player();
+                for edge in &self.nodes[idx].children {
+                    if played.contains(&edge.mv) {
+                        let value = self.value(edge.child, player, &result, rewards);
+                        let child = &self.nodes[edge.child];
+                        child.amaf_n.add(1);
+                        child.amaf_w.add(value);
+                    }
+                }
+            }
+
+            if let Some(parent) = parent {
+                let edge = self.nodes[parent]
                     .children
                     .iter()
-                    .map(|&c| (self.uct(c, nidx), c))
-                    .max_by(|v1, v2| v1.0.total_cmp(&v2.0));
-                if let Some(best_uct) = best_uct_opt {
-                    nidx = best_uct.1;
-                } else {
-                    unreachable!()
+                    .find(|e| e.child == idx)
+                    .unwrap();
+                edge.visits.add(1);
+                if amaf {
+                    played.push(edge.mv);
                 }
             }
+            self.update_value(idx);
         }
-
-        nidx
     }
 
-    /// Creates all children for a given node index and returns their indexes.
-    pub fn expand(&mut self, idx: usize) -> Vec<usize> {
-        let state = self[idx].state.clone();
+    /// Recompute the value of `idx` from its leaf results and its children, see
+    /// `backpropagate`.
+    fn update_value(&self, idx: usize) {
+        let node = &self.nodes[idx];
+        let (q, opponent_q) = if self.observations {
+            // every node of the tree has its own point of view, see `observations`
+            let mean = node.w.get() / (node.n.get() - 1).max(1) as f64;
+            (mean, mean)
+        } else if node.chance {
+            (
+                self.expected_value(idx, |c| c.q.get()).unwrap_or(0.0),
+                self.expected_value(idx, |c| c.opponent_q.get())
+                    .unwrap_or(0.0),
+            )
+        } else {
+            let leaf = (
+                node.leaf_n.get() as f64,
+                node.leaf_w.get(),
+                node.leaf_opponent_w.get(),
+            );
+            let (visits, reward, opponent_reward) = node
+                .children
+                .iter()
+                .map(|e| (e, e.visits.get()))
+                .filter(|&(_, visits)| visits > 1)
+                .fold(
+                    leaf,
+                    |(visits, reward, opponent_reward), (e, edge_visits)| {
+                        let child = &self.nodes[e.child];
+                        let edge_visits = (edge_visits - 1) as f64;
+                        (
+                            visits + edge_visits,
+                            reward + edge_visits * child.opponent_q.get(),
+                            opponent_reward + edge_visits * child.q.get(),
+                        )
+                    },
+                );
+            if visits > 0.0 {
+                (reward / visits, opponent_reward / visits)
+            } else {
+                (0.0, 0.0)
+            }
+        };
+        let node = &self.nodes[idx];
+        node.q.set(q);
+        node.opponent_q.set(opponent_q);
+    }
 
-        state
-            .all_moves()
-            .into_iter()
-            .map(|m| state.apply_move(m))
-            .map(|s| Node::new(s, Some(idx)))
-            .map(|n| self.add_node_with_parent(n))
-            .collect()
+    /// Keep the trajectory of `path` followed by `moves` as the best one if the terminal state it
+    /// reached has the highest `score` so far.
+    fn record_trajectory(&self, path: &[usize], score: f64, moves: &[T::Move]) {
+        let mut best = self.best.lock().unwrap();
+        if best.as_ref().is_some_and(|best| best.score >= score) {
+            return;
+        }
+        let moves = path
+            .windows(2)
+            .map(|w| {
+                let edge = self.nodes[w[0]].children.iter().find(|e| e.child == w[1]);
+                edge.unwrap().mv
+            })
+            .chain(moves.iter().copied())
+            .collect();
+        *best = Some(Trajectory { score, moves });
     }
 
-    pub fn random_playout<R: Rng>(&self, n: usize, rng: &mut R) -> <T as GameState>::UserData {
-        let mut state = self[n].state.clone();
-        loop {
-            let reward = state.is_terminal_state();
-            if let Some(r) = reward {
-                return r;
+    /// The children of `idx` as `(move, child index, statistics through the edge)` to report.
+    /// Unlike `child_stats`, the visits are those backpropagated through the edge, without the
+    /// visit every edge starts with, and the rewards are those of these visits.
+    fn edges(&self, idx: usize) -> impl Iterator<Item = (T::Move, usize, Stats)> + '_ {
+        self.nodes[idx].children.iter().map(move |e| {
+            let child = &self[e.child];
+            let visits = e.visits.get() - 1;
+            let value = self
+                .expected_value(e.child, |c| c.q.get())
+                .unwrap_or(child.q.get());
+            let n = child.n.get();
+            let reward_sq = if n > 1 {
+                child.w2.get() * visits as f64 / (n - 1) as f64
             } else {
-                let m = state.random_move(rng).unwrap();
-                state = state.apply_move(m);
+                0.0
+            };
+            let stats = Stats {
+                visits,
+                reward: value * visits as f64,
+                reward_sq,
+                prior: e.prior,
+                ..Stats::default()
+            };
+            (e.mv, e.child, stats)
+        })
+    }
+
+    /// The principal variation: the most visited line from the root, up to `depth` moves.
+    pub fn principal_variation(&self, depth: usize) -> Vec<T::Move> {
+        principal_variation(&[self], vec![Some(0)], self.nodes[0].state.clone(), depth)
+    }
+
+    /// The `k` most visited root moves, each with its statistics and followed by its principal
+    /// variation, for lines of up to `depth` moves. Moves that were never visited are left out.
+    pub fn multi_pv(&self, k: usize, depth: usize) -> Vec<Line<T::Move>> {
+        multi_pv(&[self], k, depth)
+    }
+
+    /// Re-root the tree at the root child reached with `m`, keeping the nodes reachable from it
+    /// and dropping the others. Without such a child the tree restarts from the state after `m`.
+    /// For ISMCTS `m` is applied to the information set rather than to the last determinization.
+    pub fn advance(&mut self, m: T::Move) {
+        let root = self
+            .information_set
+            .as_ref()
+            .unwrap_or(&self.nodes[0].state);
+        let state = root.apply_move(m);
+        self.advance_to(m, state);
+    }
+
+    /// Same as `advance` with `state` the state actually reached with `m`, such as the outcome
+    /// observed after a random move. In open-loop mode and for ISMCTS `state` becomes the state
+    /// of the new root. In closed-loop mode the subtree is kept as it was built, except that a
+    /// state with a different `GameState::hash` reorients the root, dropping the children
+    /// without an equivalent move.
+    pub fn advance_to(&mut self, m: T::Move, state: T) {
+        let best = self.best.get_mut().unwrap();
+        *best = best.take().and_then(|mut best| {
+            (best.moves.first() == Some(&m)).then(|| {
+                best.moves.remove(0);
+                best
+            })
+        });
+        let root = &self.nodes[0];
+        let child = root.children.iter().find(|e| e.mv == m).map(|e| e.child);
+        // in observation mode the move may lead to a child per observation
+        let child = match child {
+            Some(child) if self.observations && self.nodes[child].chance => self.nodes[child]
+                .children
+                .iter()
+                .map(|e| e.child)
+                .find(|&c| self.nodes[c].state.observation() == state.observation()),
+            child => child,
+        };
+        let Some(child) = child else {
+            self.nodes.clear();
+            self.transpositions.clear();
+            self.add_node(Node::new(state));
+            return;
+        };
+
+        // keep the reachable nodes in breadth first order so that the new root ends up at
+        // index 0
+        let mut remap = vec![usize::MAX; self.nodes.len()];
+        let mut order = vec![child];
+        remap[child] = 0;
+        let mut i = 0;
+        while i < order.len() {
+            for e in &self.nodes[order[i]].children {
+                if remap[e.child] == usize::MAX {
+                    remap[e.child] = order.len();
+                    order.push(e.child);
+                }
             }
+            i += 1;
+        }
+
+        let mut nodes = std::mem::take(&mut self.nodes)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        self.nodes = order
+            .iter()
+            .map(|&idx| {
+                let mut n = nodes[idx].take().unwrap();
+                n.parents.retain(|&p| remap[p] != usize::MAX);
+                n.parents.iter_mut().for_each(|p| *p = remap[*p]);
+                n.children.iter_mut().for_each(|e| e.child = remap[e.child]);
+                n
+            })
+            .collect();
+        self.transpositions
+            .retain(|_, idx| remap[*idx] != usize::MAX);
+        self.transpositions
+            .values_mut()
+            .for_each(|idx| *idx = remap[*idx]);
+
+        // the child may have been reached first in another orientation, or simulated with
+        // another outcome in open-loop mode
+        if self.open_loop {
+            if self.ismcts {
+                self.information_set = Some(state.clone());
+            }
+            self.nodes[0].state = state;
+        } else if self.nodes[0].state.hash() != state.hash() {
+            self.reorient_root(state);
         }
     }
 
-    pub fn backpropagate(&mut self, idx: usize, result: <T as GameState>::UserData) {
-        let mut node = &mut self[idx];
-        loop {
-            node.n += 1;
-            if node.state.terminal_is_win(&result) {
-                node.w += 1;
+    /// Replace the state of the root with `state`, a symmetric equivalent, and express the moves
+    /// of its edges in the orientation of `state`. Edges without an equivalent move are dropped
+    /// and the untried moves are listed again.
+    fn reorient_root(&mut self, state: T) {
+        let mut moves = self.reoriented_moves(0, &state).into_iter();
+        let mut children = std::mem::take(&mut self.nodes[0].children);
+        children.retain_mut(|e| match moves.next().unwrap() {
+            Some(m) => {
+                e.mv = m;
+                true
             }
-            match node.parent {
-                Some(parent) => node = &mut self[parent],
-                None => break,
+            None => {
+                self.nodes[e.child].parents.retain(|&p| p != 0);
+                false
             }
+        });
+
+        let root = &mut self.nodes[0];
+        root.children = children;
+        root.state = state;
+        root.untried = None;
+    }
+
+    /// The moves of the edges of `idx` expressed from `state`, a symmetric equivalent of its
+    /// state, `None` for an edge without an equivalent move.
+    fn reoriented_moves(&self, idx: usize, state: &T) -> Vec<Option<T::Move>> {
+        let node = &self.nodes[idx];
+        if self.open_loop || node.state.hash() == state.hash() {
+            return node.children.iter().map(|e| Some(e.mv)).collect();
         }
+        let moves = match state.chance_outcomes() {
+            Some(outcomes) => outcomes.into_iter().map(|(m, _)| m).collect(),
+            None => state.all_moves(),
+        };
+        let keys = moves
+            .iter()
+            .map(|&m| transposition_key(&state.apply_move(m)))
+            .collect::<Vec<_>>();
+        node.children
+            .iter()
+            .map(|e| {
+                let key = transposition_key(&self.nodes[e.child].state);
+                keys.iter().position(|&k| k == key).map(|i| moves[i])
+            })
+            .collect()
     }
 }
 
-impl<T: GameState> Index<usize> for Tree<T> {
+impl<T: GameState, P: SelectionPolicy, E: Evaluator<T>> Index<usize> for Tree<T, P, E> {
     type Output = Node<T>;
 
     fn index(&self, index: usize) -> &Self::Output {
@@ -179,20 +1371,157 @@ impl<T: GameState> Index<usize> for Tree<T> {
     }
 }
 
-impl<T: GameState> IndexMut<usize> for Tree<T> {
+impl<T: GameState, P: SelectionPolicy, E: Evaluator<T>> IndexMut<usize> for Tree<T, P, E> {
     fn index_mut(&mut self, index: usize) -> &mut Self::Output {
         &mut self.nodes[index]
     }
 }
 
+/// What a search thread hands back when it finishes.
+struct ThreadResult<T: GameState> {
+    iterations: u32,
+    /// proof of the root, if the solver proved it
+    proof: Option<Proof>,
+    root: Vec<RootChild<T>>,
+    best: Option<Trajectory<T::Move>>,
+}
+
+/// Statistics of a root child gathered by a search thread, or of any child when merging trees.
+struct RootChild<T: GameState> {
+    mv: T::Move,
+    visits: u32,
+    /// sum of the rewards of the player to move at the root
+    reward: f64,
+    /// sum of the squared rewards
+    reward_sq: f64,
+    proof: Option<Proof>,
+}
+
+impl<T: GameState> RootChild<T> {
+    /// Mean reward, `None` if the move was never visited.
+    fn mean(&self) -> Option<f64> {
+        (self.visits > 0).then(|| self.reward / self.visits as f64)
+    }
+
+    fn stats(&self) -> MoveStats<T::Move> {
+        let confidence_interval = match self.mean() {
+            None => (f64::NEG_INFINITY, f64::INFINITY),
+            Some(mean) => {
+                let n = self.visits as f64;
+                let variance = (self.reward_sq / n - mean * mean).max(0.0);
+                let margin = 1.96 * (variance / n).sqrt();
+                (mean - margin, mean + margin)
+            }
+        };
+        MoveStats {
+            mv: self.mv,
+            visits: self.visits,
+            mean: self.mean(),
+            confidence_interval,
+            proven: self.proof,
+        }
+    }
+}
+
+/// How the move to play is picked among the root children once the search is over. A proven win
+/// is always played and proven losses are avoided when possible.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FinalMoveSelection {
+    /// The most visited move (robust child).
+    #[default]
+    MaxVisits,
+    /// The move with the highest mean reward (max child).
+    MaxValue,
+    /// The move ranked best by both visits and mean reward (robust-max child). When no move is
+    /// first on both, the move whose worse rank is the best, ties going to the most visited.
+    RobustMax,
+    /// The move with the highest lower confidence bound `mean - a / sqrt(visits)` (secure
+    /// child).
+    Secure { a: f64 },
+}
+
+impl FinalMoveSelection {
+    /// Position of the move to play among `children`, which cannot be empty.
+    fn select<T: GameState>(&self, children: &[&RootChild<T>]) -> usize {
+        let visits = |c: &RootChild<T>| c.visits as f64;
+        // unvisited moves come last
+        let mean = |c: &RootChild<T>| c.mean().unwrap_or(f64::NEG_INFINITY);
+        match *self {
+            FinalMoveSelection::MaxVisits => policy::argmax(children.iter().map(|c| visits(c))),
+            FinalMoveSelection::MaxValue => policy::argmax(children.iter().map(|c| mean(c))),
+            FinalMoveSelection::RobustMax => {
+                // rank of a child by `key`, 0 being the best
+                let rank = |c: &RootChild<T>, key: &dyn Fn(&RootChild<T>) -> f64| {
+                    children.iter().filter(|o| key(o) > key(c)).count()
+                };
+                (0..children.len())
+                    .min_by_key(|&i| {
+                        let c = children[i];
+                        (rank(c, &visits).max(rank(c, &mean)), Reverse(c.visits))
+                    })
+                    .unwrap()
+            }
+            FinalMoveSelection::Secure { a } => {
+                policy::argmax(children.iter().map(|c| mean(c) - a / visits(c).sqrt()))
+            }
+        }
+    }
+}
+
 pub struct BestResultHandle<T: GameState> {
-    threads: Vec<JoinHandle<(u32, Vec<u32>)>>,
-    initial_move_set: Vec<T::Move>,
+    threads: Vec<JoinHandle<ThreadResult<T>>>,
+    selection: FinalMoveSelection,
 }
 
 pub struct BestResult<T: GameState> {
     pub iterations: u32,
     pub best_move: <T as GameState>::Move,
+    /// Outcome of the game for the player to move, when the solver proved it.
+    pub proven: Option<Proof>,
+    /// The best complete trajectory found, in single-player mode.
+    pub best_trajectory: Option<Trajectory<T::Move>>,
+    /// Statistics of every root move merged over all threads, the most visited first.
+    pub moves: Vec<MoveStats<T::Move>>,
+}
+
+impl<T: GameState> BestResult<T> {
+    /// Share of the root visits of every move, in the order of `moves`.
+    pub fn visit_distribution(&self) -> Vec<(T::Move, f64)> {
+        let total = self.moves.iter().map(|m| m.visits).sum::<u32>().max(1) as f64;
+        self.moves
+            .iter()
+            .map(|m| (m.mv, m.visits as f64 / total))
+            .collect()
+    }
+}
+
+/// A root move with its statistics and the line expected to follow, see `Search::multi_pv`.
+#[derive(Clone, Debug)]
+pub struct Line<M> {
+    pub stats: MoveStats<M>,
+    /// the root move followed by its principal variation
+    pub moves: Vec<M>,
+}
+
+/// Statistics of a root move at the end of a search.
+#[derive(Clone, Debug)]
+pub struct MoveStats<M> {
+    pub mv: M,
+    pub visits: u32,
+    /// mean reward of the player to move at the root, `None` for an unvisited move
+    pub mean: Option<f64>,
+    /// 95% confidence interval of the mean, `mean ± 1.96 * sqrt(variance / visits)`, unbounded
+    /// for an unvisited move
+    pub confidence_interval: (f64, f64),
+    /// Outcome of this move for the player to move, when the solver proved it.
+    pub proven: Option<Proof>,
+}
+
+/// Moves from the root to a terminal state and the score of that state.
+#[derive(Clone, Debug)]
+pub struct Trajectory<M> {
+    pub score: f64,
+    pub moves: Vec<M>,
 }
 
 impl<T: GameState> BestResultHandle<T> {
@@ -201,45 +1530,81 @@ impl<T: GameState> BestResultHandle<T> {
     }
 
     pub fn join(self) -> BestResult<T> {
+        // root moves can differ between threads, merge the visits by move
         let results = self
             .threads
             .into_iter()
             .map(|t| t.join().unwrap())
-            .reduce(|acc, val| {
-                let iters = acc.0 + val.0;
-                let vals = acc.1.into_iter().zip(val.1).map(|(a, b)| a + b).collect();
-                (iters, vals)
+            .reduce(|mut acc, val| {
+                acc.iterations += val.iterations;
+                acc.proof = acc.proof.or(val.proof);
+                if val.best.as_ref().map(|t| t.score) > acc.best.as_ref().map(|t| t.score) {
+                    acc.best = val.best;
+                }
+                for child in val.root {
+                    match acc.root.iter_mut().find(|c| c.mv == child.mv) {
+                        Some(c) => {
+                            c.visits += child.visits;
+                            c.reward += child.reward;
+                            c.reward_sq += child.reward_sq;
+                            c.proof = c.proof.or(child.proof);
+                        }
+                        None => acc.root.push(child),
+                    }
+                }
+                acc
             })
             .unwrap();
 
-        let iterations = results.0;
+        let iterations = results.iterations;
 
-        let best_move_idx = results
-            .1
-            .into_iter()
-            .enumerate()
-            .max_by_key(|t| t.1)
-            .unwrap()
-            .0;
-
-        let best_move = self.initial_move_set[best_move_idx];
+        // play a proven win if there is one and avoid proven losses if possible
+        let root = results.root;
+        let mut candidates = root
+            .iter()
+            .filter(|c| c.proof != Some(Proof::Loss))
+            .collect::<Vec<_>>();
+        if candidates.is_empty() {
+            candidates = root.iter().collect();
+        }
+        let best_move = match root.iter().find(|c| c.proof == Some(Proof::Win)) {
+            Some(win) => win.mv,
+            None => candidates[self.selection.select(&candidates)].mv,
+        };
+        let mut moves = root.iter().map(RootChild::stats).collect::<Vec<_>>();
+        moves.sort_by_key(|m| Reverse(m.visits));
 
         BestResult {
             iterations,
             best_move,
+            proven: results.proof.map(Proof::reverse),
+            best_trajectory: results.best,
+            moves,
         }
     }
 }
 
-pub struct MCTS<R>
+pub struct MCTS<R, P = Ucb1, E = RandomPlayout>
 where
     R: RngProvider,
+    P: SelectionPolicy,
 {
     num_threads: usize,
-    exploration_factor: f64,
+    policy: P,
+    evaluator: E,
+    expansion: Expansion,
+    solver: bool,
+    open_loop: bool,
+    ismcts: bool,
+    single_player: bool,
+    final_move_selection: FinalMoveSelection,
+    parallelism: Parallelism,
+    batch_size: usize,
+    batch_timeout: Duration,
     rng_type: PhantomData<R>,
 }
 
+#[deprecated(note = "use `MCTS::run_with_end_condition`")]
 pub fn run_with_end_condition<T, R>(
     exploration_factor: f64,
     state: T,
@@ -248,117 +1613,794 @@ pub fn run_with_end_condition<T, R>(
 ) -> BestResultHandle<T>
 where
     T: GameState + Send + Sync + 'static,
+    T::Move: Send + Sync,
+    T::UserData: Send,
     R: RngProvider,
 {
-    let initial_move_set = state.all_moves();
-
-    let threads = (0..nthreads)
-        .map(|_| {
-            let state = state.clone();
-            let mut rng = R::init();
-            thread::spawn(move || {
-                let mut iterations = 0;
-                let mut tree = Tree::new(exploration_factor);
-                let n = Node::new(state, None);
-                tree.add_node_with_parent(n);
-
-                loop {
-                    let selection_idx = tree.select();
-                    let terminal = tree[selection_idx].state.is_terminal_state();
-
-                    // if terminal state, backprogagate it otherwise expand
-                    if let Some(reward) = terminal {
-                        tree.backpropagate(selection_idx, reward);
-                    } else {
-                        let new_children = tree.expand(selection_idx);
-
-                        let random_child_idx = rng.gen_range(0..new_children.len());
-                        let child_selection = new_children[random_child_idx];
-
-                        let result = tree.random_playout(child_selection, &mut rng);
-
-                        tree.backpropagate(child_selection, result);
-                    }
-
-                    if end_condition(nthreads, iterations) {
-                        break;
-                    }
-
-                    iterations += 1;
-                }
-                (
-                    iterations,
-                    tree[0]
-                        .children
-                        .iter()
-                        .map(|&idx| tree[idx].n)
-                        .collect::<Vec<u32>>(),
-                )
-            })
-        })
-        .collect::<Vec<_>>();
-
-    BestResultHandle {
-        threads,
-        initial_move_set,
-    }
+    MCTS::<R>::default()
+        .exploration_factor(exploration_factor)
+        .num_threads(nthreads)
+        .run_with_end_condition(state, end_condition)
 }
 
-impl<R> MCTS<R>
+impl<R, P, E> MCTS<R, P, E>
 where
     R: RngProvider,
+    P: SelectionPolicy,
+    E: Clone + Send + 'static,
 {
     pub fn num_threads(mut self, num_threads: usize) -> Self {
         self.num_threads = num_threads;
         self
     }
 
-    pub fn exploration_factor(mut self, exploration_factor: f64) -> Self {
-        self.exploration_factor = exploration_factor;
+    /// Whether threads search their own tree (the default) or share one.
+    pub fn parallelism(mut self, parallelism: Parallelism) -> Self {
+        self.parallelism = parallelism;
+        self
+    }
+
+    /// Create children all at once (the default) or one at a time.
+    pub fn expansion(mut self, expansion: Expansion) -> Self {
+        self.expansion = expansion;
+        self
+    }
+
+    /// Enable MCTS-Solver: terminal states are proven wins, losses or draws, proofs propagate up
+    /// the tree with minimax rules and proven nodes are no longer searched. A thread stops once
+    /// it proved the root. This assumes a two player, zero sum game where players alternate.
+    pub fn solver(mut self, solver: bool) -> Self {
+        self.solver = solver;
+        self
+    }
+
+    /// Open-loop search for stochastic simulators: nodes stand for move sequences rather than
+    /// states, and the states along the path are simulated again from the root on every
+    /// iteration with `GameState::apply_move_with_rng`. The moves of a node are listed from the
+    /// first state simulated through it. Transpositions and the solver are not used in this
+    /// mode.
+    pub fn open_loop(mut self, open_loop: bool) -> Self {
+        self.open_loop = open_loop;
         self
     }
 
+    /// Single observer Information Set MCTS for games with hidden information. Every iteration
+    /// searches a new determinization of the root state (see `GameState::determinize`) as seen
+    /// by the player to move, so the search does not use information hidden from them. Nodes
+    /// stand for moves as in open-loop mode, only the moves legal in the current determinization
+    /// are selected and their availability replaces the visits of the parent in the selection
+    /// policy. New children are created one at a time whatever the expansion mode.
+    pub fn ismcts(mut self, ismcts: bool) -> Self {
+        self.ismcts = ismcts;
+        self
+    }
+
+    /// Single-player mode for puzzles and optimization problems: `terminal_reward` of a terminal
+    /// state is its score and need not lie in `[0, 1]`, the best complete trajectory found and
+    /// its score are returned in `BestResult::best_trajectory`. Every node on the path of a
+    /// playout is credited with the score of the terminal state it reached. The solver is not
+    /// used in this mode, `policy::SpMcts` accounts for the variance of the scores.
+    pub fn single_player(mut self, single_player: bool) -> Self {
+        self.single_player = single_player;
+        self
+    }
+
+    /// How the move to play is picked among the root children, the most visited by default.
+    pub fn final_move_selection(mut self, final_move_selection: FinalMoveSelection) -> Self {
+        self.final_move_selection = final_move_selection;
+        self
+    }
+
+    /// Use a different selection policy to descend the tree.
+    pub fn policy<Q: SelectionPolicy>(self, policy: Q) -> MCTS<R, Q, E> {
+        MCTS {
+            num_threads: self.num_threads,
+            policy,
+            evaluator: self.evaluator,
+            expansion: self.expansion,
+            solver: self.solver,
+            open_loop: self.open_loop,
+            ismcts: self.ismcts,
+            single_player: self.single_player,
+            final_move_selection: self.final_move_selection,
+            parallelism: self.parallelism,
+            batch_size: self.batch_size,
+            batch_timeout: self.batch_timeout,
+            rng_type: PhantomData,
+        }
+    }
+
+    /// Score leaves with an evaluator instead of plain random playouts.
+    pub fn evaluator<F>(self, evaluator: F) -> MCTS<R, P, F> {
+        MCTS {
+            num_threads: self.num_threads,
+            policy: self.policy,
+            evaluator,
+            expansion: self.expansion,
+            solver: self.solver,
+            open_loop: self.open_loop,
+            ismcts: self.ismcts,
+            single_player: self.single_player,
+            final_move_selection: self.final_move_selection,
+            parallelism: self.parallelism,
+            batch_size: self.batch_size,
+            batch_timeout: self.batch_timeout,
+            rng_type: PhantomData,
+        }
+    }
+
+    /// Evaluate leaves in batches of at least `batch_size` states with
+    /// `Evaluator::evaluate_batch`. Leaves are gathered from all threads, each thread contributing
+    /// several descents spread out with virtual loss.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// How long a thread waits for other threads to fill a batch before evaluating a partial one.
+    pub fn batch_timeout(mut self, batch_timeout: Duration) -> Self {
+        self.batch_timeout = batch_timeout;
+        self
+    }
+
+    /// A new tree for `state`, set up with the options of this search.
+    fn new_tree<T>(&self, state: T) -> Tree<T, P, E>
+    where
+        T: GameState,
+        E: Evaluator<T>,
+    {
+        let mut tree = Tree::new(self.policy.clone(), self.evaluator.clone())
+            .expansion(self.expansion)
+            .solver(self.solver && !self.open_loop && !self.ismcts && !self.single_player)
+            .open_loop(self.open_loop)
+            .ismcts(self.ismcts)
+            .single_player(self.single_player);
+        tree.add_node(Node::new(state));
+        tree
+    }
+
+    /// A search of `state` that keeps its trees between runs, see `Search::advance`.
+    pub fn search<T>(&self, state: T) -> Search<T, R, P, E>
+    where
+        T: GameState + Send + Sync + 'static,
+        T::Move: Send + Sync,
+        T::UserData: Send,
+        P: Sync,
+        E: Evaluator<T> + Sync,
+    {
+        Search::new(self.clone(), state)
+    }
+
+    pub fn run_with_end_condition<T>(
+        &self,
+        state: T,
+        end_condition: impl Fn(usize, u32) -> bool + Send + Copy + 'static,
+    ) -> BestResultHandle<T>
+    where
+        T: GameState + Send + Sync + 'static,
+        T::Move: Send + Sync,
+        T::UserData: Send,
+        P: Sync,
+        E: Evaluator<T> + Sync,
+    {
+        self.search(state).run_with_end_condition(end_condition)
+    }
+
     #[cfg(feature = "chrono")]
     pub fn run_with_duration<T>(&self, state: T, duration: chrono::TimeDelta) -> BestResultHandle<T>
     where
         T: GameState + Send + Sync + 'static,
+        T::Move: Send + Sync,
+        T::UserData: Send,
+        P: Sync,
+        E: Evaluator<T> + Sync,
     {
-        let end_time = chrono::Utc::now() + duration;
-
-        run_with_end_condition::<T, R>(
-            self.exploration_factor,
-            state,
-            move |_, _| chrono::Utc::now() >= end_time,
-            self.num_threads,
-        )
+        self.search(state).run_with_duration(duration)
     }
 
     pub fn run_with_iterations<T>(&self, state: T, num_iterations: u32) -> BestResultHandle<T>
     where
         T: GameState + Send + Sync + 'static,
+        T::Move: Send + Sync,
+        T::UserData: Send,
+        P: Sync,
+        E: Evaluator<T> + Sync,
     {
-        run_with_end_condition::<T, R>(
-            self.exploration_factor,
-            state,
-            move |nthreads, iters| iters >= num_iterations / nthreads as u32,
-            self.num_threads,
-        )
+        self.search(state).run_with_iterations(num_iterations)
+    }
+}
+
+impl<R, E> MCTS<R, Ucb1, E>
+where
+    R: RngProvider,
+{
+    pub fn exploration_factor(mut self, exploration_factor: f64) -> Self {
+        self.policy = Ucb1::new(exploration_factor);
+        self
     }
 }
 
-impl<R: RngProvider> Default for MCTS<R> {
+impl<R, P, E> Clone for MCTS<R, P, E>
+where
+    R: RngProvider,
+    P: SelectionPolicy,
+    E: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            num_threads: self.num_threads,
+            policy: self.policy.clone(),
+            evaluator: self.evaluator.clone(),
+            expansion: self.expansion,
+            solver: self.solver,
+            open_loop: self.open_loop,
+            ismcts: self.ismcts,
+            single_player: self.single_player,
+            final_move_selection: self.final_move_selection,
+            parallelism: self.parallelism,
+            batch_size: self.batch_size,
+            batch_timeout: self.batch_timeout,
+            rng_type: PhantomData,
+        }
+    }
+}
+
+impl<R, P, E> Default for MCTS<R, P, E>
+where
+    R: RngProvider,
+    P: SelectionPolicy + Default,
+    E: Default,
+{
     fn default() -> Self {
         #[cfg(feature = "multi-threaded")]
         let num_threads = num_cpus::get();
         #[cfg(not(feature = "multi-threaded"))]
         let num_threads = 1;
 
-        let exploration_factor = default_exploration_constant();
-
         Self {
             num_threads,
-            exploration_factor,
+            policy: P::default(),
+            evaluator: E::default(),
+            expansion: Expansion::Full,
+            solver: false,
+            open_loop: false,
+            ismcts: false,
+            single_player: false,
+            final_move_selection: FinalMoveSelection::MaxVisits,
+            parallelism: Parallelism::Root,
+            batch_size: 1,
+            batch_timeout: Duration::from_millis(1),
             rng_type: PhantomData,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use crate::{
+        evaluator::{Evaluator, Outcome, RandomPlayout},
+        nested::NestedMonteCarlo,
+        policy::{SelectionPolicy, Stats, Ucb1},
+        rng::{Rng, RngProvider},
+        testing::{Nim, Row, TestRng},
+        transposition_key, Expansion, GameState, Node, Proof, Trajectory, Tree, MCTS,
+    };
+
+    fn nim_tree(state: Nim) -> Tree<Nim> {
+        let mut tree = Tree::new(Ucb1::default(), RandomPlayout);
+        tree.add_node(Node::new(state));
+        tree
+    }
+
+    /// Nim with a secret that does not change the game, hidden from the players.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Secret {
+        nim: Nim,
+        secret: usize,
+    }
+
+    impl GameState for Secret {
+        type Move = u32;
+        type UserData = usize;
+
+        fn all_moves(&self) -> Vec<u32> {
+            self.nim.all_moves()
+        }
+
+        fn apply_move(&self, n: u32) -> Self {
+            Self {
+                nim: self.nim.apply_move(n),
+                ..*self
+            }
+        }
+
+        fn is_terminal_state(&self) -> Option<usize> {
+            self.nim.is_terminal_state()
+        }
+
+        fn terminal_reward(&self, winner: &usize) -> f64 {
+            self.nim.terminal_reward(winner)
+        }
+
+        fn determinize<R: Rng>(&self, _observer: usize, rng: &mut R) -> Self {
+            Self {
+                secret: rng.gen_range(0..1000),
+                ..*self
+            }
+        }
+    }
+
+    /// Run `iterations` plain select, expand, playout and backpropagate iterations on `tree`.
+    fn grow<T, P, E>(tree: &mut Tree<T, P, E>, iterations: usize)
+    where
+        T: GameState,
+        P: SelectionPolicy,
+        E: Evaluator<T>,
+    {
+        let mut rng = TestRng::init();
+        for _ in 0..iterations {
+            let mut path = tree.select(&mut rng);
+            let leaf = *path.last().unwrap();
+            if let Some(winner) = tree[leaf].state.is_terminal_state() {
+                tree.backpropagate(&path, Outcome::Terminal(winner), &[]);
+                continue;
+            }
+            let children = tree.expand(leaf, &mut rng);
+            if children.is_empty() {
+                // progressive widening drew a move that already has a child
+                path.push(tree.choose_child(leaf, &mut rng));
+            } else {
+                path.push(children[rng.gen_range(0..children.len())]);
+            }
+            let mut moves = Vec::new();
+            let result = tree.random_playout(*path.last().unwrap(), &mut rng, &mut moves);
+            tree.backpropagate(&path, result, &moves);
+        }
+    }
+
+    /// Nodes reachable from `idx`, `idx` included.
+    fn reachable(tree: &Tree<Nim>, idx: usize) -> usize {
+        let mut seen = vec![false; tree.nodes.len()];
+        let mut pending = vec![idx];
+        while let Some(idx) = pending.pop() {
+            if !std::mem::replace(&mut seen[idx], true) {
+                pending.extend(tree[idx].children.iter().map(|e| e.child));
+            }
+        }
+        seen.iter().filter(|&&s| s).count()
+    }
+
+    /// Every edge has a matching parent link and the other way around.
+    fn assert_linked(tree: &Tree<Nim>) {
+        for (idx, node) in tree.nodes.iter().enumerate() {
+            for e in &node.children {
+                assert!(tree[e.child].parents.contains(&idx));
+            }
+            for &p in &node.parents {
+                assert!(tree[p].children.iter().any(|e| e.child == idx));
+            }
+        }
+    }
+
+    #[test]
+    fn solver_proves_a_won_nim() {
+        // 10 - 2 is a multiple of 4, adding 2 leaves the opponent lost
+        let result = MCTS::<TestRng>::default()
+            .num_threads(1)
+            .solver(true)
+            .run_with_iterations(Nim::new(10), 100_000)
+            .join();
+        assert_eq!(result.proven, Some(Proof::Win));
+        assert_eq!(result.best_move, 2);
+        assert!(result.iterations < 100_000);
+        // the search stops as soon as the root is proven, the other moves may not be
+        for m in &result.moves {
+            match m.mv {
+                2 => assert_eq!(m.proven, Some(Proof::Win)),
+                _ => assert_ne!(m.proven, Some(Proof::Win)),
+            }
+        }
+    }
+
+    #[test]
+    fn solver_proves_a_lost_nim() {
+        let result = MCTS::<TestRng>::default()
+            .num_threads(1)
+            .solver(true)
+            .run_with_iterations(Nim::new(12), 100_000)
+            .join();
+        assert_eq!(result.proven, Some(Proof::Loss));
+        assert!(result.moves.iter().all(|m| m.proven == Some(Proof::Loss)));
+    }
+
+    #[test]
+    fn unvisited_moves_are_reported_without_visits() {
+        let result = MCTS::<TestRng>::default()
+            .num_threads(1)
+            .run_with_iterations(Nim::new(10), 1)
+            .join();
+        assert_eq!(result.moves.len(), 3);
+        assert!(result.moves.iter().any(|m| m.visits == 0));
+        let total = result.moves.iter().map(|m| m.visits).sum::<u32>();
+        let distribution = result.visit_distribution();
+        for (m, (mv, share)) in result.moves.iter().zip(distribution) {
+            assert_eq!(m.mv, mv);
+            assert_eq!(share, m.visits as f64 / total as f64);
+            match m.visits {
+                0 => {
+                    assert_eq!(m.mean, None);
+                    assert_eq!(m.confidence_interval, (f64::NEG_INFINITY, f64::INFINITY));
+                }
+                // a single playout is won or lost
+                1 => assert!(matches!(m.mean, Some(mean) if mean == 0.0 || mean == 1.0)),
+                _ => assert!(m.mean.is_some()),
+            }
+        }
+    }
+
+    #[test]
+    fn advance_keeps_only_the_subtree_of_the_move() {
+        let mut tree = nim_tree(Nim::new(10));
+        grow(&mut tree, 2000);
+        let child = tree[0].children.iter().find(|e| e.mv == 2).unwrap().child;
+        let (kept, visits) = (reachable(&tree, child), tree[child].n.get());
+        assert!(kept < tree.nodes.len());
+
+        tree.advance(2);
+        assert_eq!(tree.nodes.len(), kept);
+        assert_eq!(tree[0].n.get(), visits);
+        assert_eq!(tree[0].state.total, 2);
+        assert!(tree[0].parents.is_empty());
+        assert_linked(&tree);
+
+        // the search goes on from the kept statistics
+        grow(&mut tree, 100);
+        assert_eq!(tree[0].n.get(), visits + 100);
+    }
+
+    #[test]
+    fn advance_without_a_child_restarts_from_the_move() {
+        let mut tree = nim_tree(Nim::new(10));
+        tree.advance(3);
+        assert_eq!(tree.nodes.len(), 1);
+        assert_eq!(tree[0].state.total, 3);
+    }
+
+    #[test]
+    fn advance_remaps_the_transpositions() {
+        let mut tree = nim_tree(Nim::new(10).with_transpositions());
+        grow(&mut tree, 2000);
+        tree.advance(1);
+        tree.advance(2);
+        assert_eq!(tree[0].state.total, 3);
+        assert_linked(&tree);
+        assert_eq!(tree.transpositions.len(), tree.nodes.len());
+        for (&key, &idx) in &tree.transpositions {
+            assert_eq!(transposition_key(&tree[idx].state), Some(key));
+        }
+    }
+
+    #[test]
+    fn tree_values_are_the_mean_of_the_paths() {
+        let mut tree = nim_tree(Nim::new(10));
+        grow(&mut tree, 2000);
+        for idx in 0..tree.nodes.len() {
+            for (i, e) in tree[idx].children.iter().enumerate() {
+                let reward = tree.child_stats(idx, i).reward;
+                assert!((reward - tree[e.child].w.get()).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn transpositions_share_a_node() {
+        let mut tree = nim_tree(Nim::new(10).with_transpositions());
+        grow(&mut tree, 3000);
+        // a node per total and player who moved, the root has no transposition
+        assert!(tree.nodes.len() <= 2 * 11);
+        assert!(tree.nodes.iter().any(|n| n.parents.len() > 1));
+        assert_linked(&tree);
+
+        // every visit of a node but the first comes through one of its edges
+        for (idx, node) in tree.nodes.iter().enumerate().skip(1) {
+            let through_edges = node
+                .parents
+                .iter()
+                .flat_map(|&p| tree[p].children.iter().filter(|e| e.child == idx))
+                .map(|e| e.visits.get() - 1)
+                .sum::<u32>();
+            assert_eq!(node.n.get() - 1, through_edges);
+        }
+
+        // values take in the visits of the children through their other parents
+        let path_mean = |n: &Node<Nim>| n.w.get() / (n.n.get() - 1) as f64;
+        assert!(tree
+            .nodes
+            .iter()
+            .any(|n| n.n.get() > 1 && (n.q.get() - path_mean(n)).abs() > 1e-9));
+    }
+
+    /// Node reached from the root with `moves`.
+    fn node_after<T: GameState>(tree: &Tree<T>, moves: &[T::Move]) -> usize {
+        moves.iter().fold(0, |idx, &m| {
+            tree[idx].children.iter().find(|e| e.mv == m).unwrap().child
+        })
+    }
+
+    #[test]
+    fn grandparents_are_valued_through_shared_grandchildren() {
+        let mut tree = nim_tree(Nim::new(10).with_transpositions());
+        let mut rng = TestRng::init();
+        for moves in [&[][..], &[2], &[2, 1], &[1], &[1, 1]] {
+            tree.expand(node_after(&tree, moves), &mut rng);
+        }
+        // 1 1 2 and 2 1 1 reach the same node, below 1 1 on one side and 2 1 on the other
+        let through_grandparent = [0, 1, 2, 3].map(|i| node_after(&tree, &[1, 1, 2][..i]));
+        let elsewhere = [0, 1, 2, 3].map(|i| node_after(&tree, &[2, 1, 1][..i]));
+        let shared = elsewhere[3];
+        assert_eq!(through_grandparent[3], shared);
+
+        // the mover of the shared node also moved into the grandparent, it wins three times
+        // through the other parent and loses once through the grandparent
+        let mover = tree[shared].state.mover;
+        for _ in 0..3 {
+            tree.backpropagate(&elsewhere, Outcome::Terminal(mover), &[]);
+        }
+        tree.backpropagate(&through_grandparent, Outcome::Terminal(1 - mover), &[]);
+
+        let grandparent = &tree[through_grandparent[1]];
+        assert_eq!(grandparent.w.get(), 0.0);
+        assert!((tree[shared].q.get() - 0.75).abs() < 1e-9);
+        assert!((grandparent.q.get() - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn search_with_transpositions_finds_the_winning_move() {
+        let result = MCTS::<TestRng>::default()
+            .num_threads(1)
+            .run_with_iterations(Nim::new(10).with_transpositions(), 5000)
+            .join();
+        assert_eq!(result.best_move, 2);
+    }
+
+    #[test]
+    fn ismcts_advances_the_information_set() {
+        let secret = Secret {
+            nim: Nim::new(10),
+            secret: 7,
+        };
+        let mut tree = Tree::new(Ucb1::default(), RandomPlayout).ismcts(true);
+        tree.add_node(Node::new(secret));
+        grow(&mut tree, 500);
+        assert_ne!(tree[0].state.secret, 7);
+
+        tree.advance(1);
+        let expected = secret.apply_move(1);
+        assert_eq!(tree.information_set, Some(expected));
+        assert_eq!(tree[0].state, expected);
+    }
+
+    #[test]
+    fn open_loop_advances_to_the_observed_state() {
+        let mut tree = Tree::new(Ucb1::default(), RandomPlayout).open_loop(true);
+        tree.add_node(Node::new(Secret {
+            nim: Nim::new(10),
+            secret: 0,
+        }));
+        grow(&mut tree, 500);
+
+        let observed = Secret {
+            nim: Nim::new(10).apply_move(1),
+            secret: 42,
+        };
+        tree.advance_to(1, observed);
+        assert_eq!(tree[0].state, observed);
+        assert!(tree[0].n.get() > 1);
+    }
+
+    /// Descends into the last child, only through `choose`.
+    #[derive(Clone)]
+    struct Last;
+
+    impl SelectionPolicy for Last {
+        fn score(&self, _parent: &Stats, _child: &Stats) -> f64 {
+            unreachable!("children are only picked with choose")
+        }
+
+        fn choose<R: Rng>(&self, _parent: &Stats, children: &[Stats], _rng: &mut R) -> usize {
+            assert!(children.iter().all(|c| c.available.is_some()));
+            children.len() - 1
+        }
+    }
+
+    #[test]
+    fn ismcts_selects_with_the_policy_choice() {
+        let mut tree = Tree::new(Last, RandomPlayout).ismcts(true);
+        tree.add_node(Node::new(Secret {
+            nim: Nim::new(10),
+            secret: 0,
+        }));
+        grow(&mut tree, 200);
+        assert!(tree[0].children.iter().all(|e| e.available.get() > 0));
+    }
+
+    /// Pick four digits from 0 to 3, scored by their sum, or 100 for 3 1 2 0. The score is
+    /// only known to the terminal state.
+    #[derive(Clone, Debug)]
+    struct Digits(Vec<u32>);
+
+    impl GameState for Digits {
+        type Move = u32;
+        type UserData = ();
+
+        fn all_moves(&self) -> Vec<u32> {
+            (0..4).collect()
+        }
+
+        fn apply_move(&self, digit: u32) -> Self {
+            Digits(self.0.iter().copied().chain([digit]).collect())
+        }
+
+        fn is_terminal_state(&self) -> Option<()> {
+            (self.0.len() == 4).then_some(())
+        }
+
+        fn terminal_reward(&self, _: &()) -> f64 {
+            match self.0[..] {
+                [3, 1, 2, 0] => 100.0,
+                _ => self.0.iter().sum::<u32>() as f64,
+            }
+        }
+    }
+
+    /// Score of the state reached by playing `trajectory`.
+    fn replay(trajectory: &Trajectory<u32>) -> f64 {
+        let state = trajectory
+            .moves
+            .iter()
+            .fold(Digits(Vec::new()), |state, &m| state.apply_move(m));
+        state.terminal_reward(&state.is_terminal_state().unwrap())
+    }
+
+    #[test]
+    fn single_player_searches_score_the_terminal_state() {
+        let result = MCTS::<TestRng>::default()
+            .num_threads(1)
+            .single_player(true)
+            .run_with_iterations(Digits(Vec::new()), 2000)
+            .join();
+        let best = result.best_trajectory.unwrap();
+        assert_eq!(replay(&best), best.score);
+
+        let nested = NestedMonteCarlo::<TestRng>::default()
+            .level(1)
+            .run(Digits(Vec::new()));
+        assert_eq!(replay(&nested), nested.score);
+    }
+
+    #[test]
+    fn single_player_nodes_are_credited_with_the_score() {
+        let mut tree = Tree::new(Ucb1::default(), RandomPlayout).single_player(true);
+        tree.add_node(Node::new(Digits(Vec::new())));
+        grow(&mut tree, 1);
+        let best = tree.best_trajectory().unwrap();
+        assert_eq!(replay(&best), best.score);
+        assert!(best.score > 0.0);
+        let path = [0, node_after(&tree, &best.moves[..1])];
+        for idx in path {
+            assert_eq!(tree[idx].w.get(), best.score);
+        }
+    }
+
+    #[test]
+    fn principal_variations_replay_through_mirrored_nodes() {
+        let mut tree = Tree::new(Ucb1::default(), RandomPlayout);
+        tree.add_node(Node::new(Row::new()));
+        grow(&mut tree, 20_000);
+
+        let lines = tree.multi_pv(5, 5);
+        assert!(!lines.is_empty());
+        for line in lines {
+            let mut state = Row::new();
+            for &m in &line.moves {
+                assert!(
+                    state.all_moves().contains(&m),
+                    "{:?} in {line:?}",
+                    state.cells
+                );
+                state = state.apply_move(m);
+            }
+        }
+        let mut state = Row::new();
+        for m in tree.principal_variation(5) {
+            assert!(state.all_moves().contains(&m));
+            state = state.apply_move(m);
+        }
+    }
+
+    /// Random playouts with a prior of 0.8 on the first move, the other moves sharing the rest.
+    #[derive(Clone)]
+    struct Skewed;
+
+    impl Evaluator<Nim> for Skewed {
+        type Estimate = Infallible;
+
+        fn evaluate(&self, _state: &Nim, _depth: usize) -> Option<Infallible> {
+            None
+        }
+
+        fn estimate_value(&self, _state: &Nim, estimate: &Infallible) -> f64 {
+            match *estimate {}
+        }
+
+        fn move_priors(&self, _state: &Nim, moves: &[u32]) -> Vec<f64> {
+            let others = 0.2 / (moves.len() as f64 - 1.0).max(1.0);
+            (0..moves.len())
+                .map(|i| if i == 0 { 0.8 } else { others })
+                .collect()
+        }
+    }
+
+    /// Every edge of `tree` has the prior `Skewed` gives its move among all the moves.
+    fn assert_skewed_priors<P: SelectionPolicy>(tree: &Tree<Nim, P, Skewed>) {
+        for node in &tree.nodes {
+            let moves = node.state.all_moves();
+            let priors = Skewed.move_priors(&node.state, &moves);
+            for e in &node.children {
+                let i = moves.iter().position(|&m| m == e.mv).unwrap();
+                assert_eq!(e.prior, priors[i]);
+            }
+        }
+    }
+
+    #[test]
+    fn progressive_widening_priors_are_normalized_over_all_moves() {
+        let widening = Expansion::ProgressiveWidening { k: 1.0, alpha: 0.5 };
+        let mut tree = Tree::new(Ucb1::default(), Skewed).expansion(widening);
+        tree.add_node(Node::new(Nim::new(10)));
+        grow(&mut tree, 500);
+        assert!(tree[0].children.len() > 1);
+        assert_skewed_priors(&tree);
+    }
+
+    #[test]
+    fn ismcts_priors_are_normalized_over_all_moves() {
+        let mut tree = Tree::new(Ucb1::default(), Skewed).ismcts(true);
+        tree.add_node(Node::new(Nim::new(10)));
+        grow(&mut tree, 500);
+        assert_eq!(tree[0].children.len(), 3);
+        assert_skewed_priors(&tree);
+    }
+
+    #[test]
+    fn lines_stop_at_unvisited_moves() {
+        let mut tree = nim_tree(Nim::new(10));
+        grow(&mut tree, 2);
+        let visited = tree[0]
+            .children
+            .iter()
+            .filter(|e| e.visits.get() > 1)
+            .count();
+        assert!(visited < tree[0].children.len());
+
+        // every move of a line was visited and the line goes on as long as there is one
+        let assert_visited_line = |moves: &[u32]| {
+            let mut idx = 0;
+            for &m in moves {
+                let e = tree[idx].children.iter().find(|e| e.mv == m).unwrap();
+                assert!(e.visits.get() > 1);
+                idx = e.child;
+            }
+            assert!(tree[idx].children.iter().all(|e| e.visits.get() == 1));
+        };
+        let lines = tree.multi_pv(3, 5);
+        assert_eq!(lines.len(), visited);
+        for line in lines {
+            assert!(line.stats.visits > 0);
+            assert_visited_line(&line.moves);
+        }
+        assert_visited_line(&tree.principal_variation(5));
+    }
+}
@@ -0,0 +1,291 @@
+//! Nested Monte Carlo Search (Cazenave, 2009) and Nested Rollout Policy Adaptation (Rosin, 2011)
+//! for deterministic single player games. Like `MCTS::single_player`, `GameState::terminal_reward`
+//! is the score of a terminal state, and both searches return the best trajectory found.
+
+use std::{collections::HashMap, marker::PhantomData};
+
+use crate::{
+    rng::{self, Rng, RngProvider},
+    GameState, Trajectory,
+};
+
+/// Nested Monte Carlo Search: at level `n` every move of the current state is scored with a
+/// search of level `n - 1`, level 0 being a random playout, and the best sequence found so far
+/// is followed one move at a time.
+pub struct NestedMonteCarlo<R: RngProvider> {
+    level: usize,
+    rng_type: PhantomData<R>,
+}
+
+impl<R: RngProvider> Clone for NestedMonteCarlo<R> {
+    fn clone(&self) -> Self {
+        Self {
+            level: self.level,
+            rng_type: PhantomData,
+        }
+    }
+}
+
+impl<R: RngProvider> Default for NestedMonteCarlo<R> {
+    fn default() -> Self {
+        Self {
+            level: 2,
+            rng_type: PhantomData,
+        }
+    }
+}
+
+impl<R: RngProvider> NestedMonteCarlo<R> {
+    /// Nesting level of the search, 2 by default. Each level multiplies the work by about the
+    /// number of moves times the length of a game.
+    pub fn level(mut self, level: usize) -> Self {
+        self.level = level;
+        self
+    }
+
+    pub fn run<T: GameState>(&self, state: T) -> Trajectory<T::Move> {
+        nested(state, self.level, &mut R::init())
+    }
+}
+
+/// Search of `level` from `state`, returning the best trajectory from `state`.
+fn nested<T: GameState, R: Rng>(mut state: T, level: usize, rng: &mut R) -> Trajectory<T::Move> {
+    if level == 0 {
+        return playout(state, rng, |state, rng| state.random_move(rng).unwrap());
+    }
+
+    let mut played = Vec::new();
+    let mut best: Option<Trajectory<T::Move>> = None;
+    loop {
+        if let Some(condition) = state.is_terminal_state() {
+            let score = state.terminal_reward(&condition);
+            return match best {
+                Some(best) if best.score > score => best,
+                _ => Trajectory {
+                    score,
+                    moves: played,
+                },
+            };
+        }
+
+        for m in state.all_moves() {
+            let trajectory = nested(state.apply_move(m), level - 1, rng);
+            if best.as_ref().is_none_or(|b| trajectory.score > b.score) {
+                let moves = played
+                    .iter()
+                    .copied()
+                    .chain([m])
+                    .chain(trajectory.moves)
+                    .collect();
+                best = Some(Trajectory {
+                    score: trajectory.score,
+                    moves,
+                });
+            }
+        }
+
+        // follow the best sequence, which starts with the moves played so far
+        let m = best.as_ref().unwrap().moves[played.len()];
+        state = state.apply_move(m);
+        played.push(m);
+    }
+}
+
+/// A game whose moves have codes, on which `Nrpa` learns its playout policy.
+pub trait MoveCode: GameState {
+    /// A code identifying `m` played from this state. Moves sharing a code share their weight, so
+    /// the code can include as much of the state as the move should depend on.
+    fn move_code(&self, m: Self::Move) -> u64;
+}
+
+/// Nested Rollout Policy Adaptation: playouts draw their moves from a softmax policy over the
+/// codes of the moves (see `MoveCode`). At level `n`, `iterations` searches of level
+/// `n - 1` are run and the policy is moved towards the best sequence found after each of them.
+pub struct Nrpa<R: RngProvider> {
+    level: usize,
+    iterations: usize,
+    alpha: f64,
+    rng_type: PhantomData<R>,
+}
+
+impl<R: RngProvider> Clone for Nrpa<R> {
+    fn clone(&self) -> Self {
+        Self {
+            level: self.level,
+            iterations: self.iterations,
+            alpha: self.alpha,
+            rng_type: PhantomData,
+        }
+    }
+}
+
+impl<R: RngProvider> Default for Nrpa<R> {
+    fn default() -> Self {
+        Self {
+            level: 3,
+            iterations: 100,
+            alpha: 1.0,
+            rng_type: PhantomData,
+        }
+    }
+}
+
+/// Weights of the move codes, 0 for codes never adapted.
+type Policy = HashMap<u64, f64>;
+
+impl<R: RngProvider> Nrpa<R> {
+    /// Nesting level of the search, 3 by default. A search of level `n` runs `iterations^n`
+    /// playouts.
+    pub fn level(mut self, level: usize) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Searches of the level below run by each level, 100 by default.
+    pub fn iterations(mut self, iterations: usize) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    /// Learning rate of the policy adaptation, 1 by default.
+    pub fn alpha(mut self, alpha: f64) -> Self {
+        self.alpha = alpha;
+        self
+    }
+
+    pub fn run<T: MoveCode>(&self, state: T) -> Trajectory<T::Move> {
+        self.nrpa(&state, self.level, Policy::new(), &mut R::init())
+    }
+
+    fn nrpa<T: MoveCode>(
+        &self,
+        state: &T,
+        level: usize,
+        mut policy: Policy,
+        rng: &mut R,
+    ) -> Trajectory<T::Move> {
+        if level == 0 {
+            return playout(state.clone(), rng, |state, rng| {
+                let moves = coded_moves(state);
+                let weights = moves.iter().map(|&(_, code)| weight(&policy, code).exp());
+                moves[rng::weighted_index(weights, rng)].0
+            });
+        }
+
+        let mut best: Option<Trajectory<T::Move>> = None;
+        for _ in 0..self.iterations {
+            let trajectory = self.nrpa(state, level - 1, policy.clone(), rng);
+            if best.as_ref().is_none_or(|b| trajectory.score >= b.score) {
+                best = Some(trajectory);
+            }
+            policy = self.adapt(state, &policy, &best.as_ref().unwrap().moves);
+        }
+        best.unwrap()
+    }
+
+    /// Move the policy towards playing `moves` from `state`.
+    fn adapt<T: MoveCode>(&self, state: &T, policy: &Policy, moves: &[T::Move]) -> Policy {
+        let mut adapted = policy.clone();
+        let mut state = state.clone();
+        for &m in moves {
+            let codes = coded_moves(&state);
+            let total = codes
+                .iter()
+                .map(|&(_, code)| weight(policy, code).exp())
+                .sum::<f64>();
+            for &(mv, code) in &codes {
+                let probability = weight(policy, code).exp() / total;
+                let target = if mv == m { 1.0 } else { 0.0 };
+                *adapted.entry(code).or_default() += self.alpha * (target - probability);
+            }
+            state = state.apply_move(m);
+        }
+        adapted
+    }
+}
+
+fn weight(policy: &Policy, code: u64) -> f64 {
+    policy.get(&code).copied().unwrap_or(0.0)
+}
+
+/// The moves of `state` with their code.
+fn coded_moves<T: MoveCode>(state: &T) -> Vec<(T::Move, u64)> {
+    state
+        .all_moves()
+        .into_iter()
+        .map(|m| (m, state.move_code(m)))
+        .collect()
+}
+
+/// Play `state` out to the end with the moves picked by `choose`.
+fn playout<T, R>(
+    mut state: T,
+    rng: &mut R,
+    choose: impl Fn(&T, &mut R) -> T::Move,
+) -> Trajectory<T::Move>
+where
+    T: GameState,
+    R: Rng,
+{
+    let mut moves = Vec::new();
+    loop {
+        if let Some(condition) = state.is_terminal_state() {
+            return Trajectory {
+                score: state.terminal_reward(&condition),
+                moves,
+            };
+        }
+        let m = choose(&state, rng);
+        state = state.apply_move(m);
+        moves.push(m);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MoveCode, Nrpa};
+    use crate::{testing::TestRng, GameState};
+
+    const HIDDEN: [u32; 8] = [2, 0, 3, 3, 1, 0, 2, 1];
+
+    /// Pick eight digits from 0 to 3, scored by the number of digits matching `HIDDEN`.
+    #[derive(Clone, Debug)]
+    struct Guess(Vec<u32>);
+
+    impl GameState for Guess {
+        type Move = u32;
+        type UserData = ();
+
+        fn all_moves(&self) -> Vec<u32> {
+            (0..4).collect()
+        }
+
+        fn apply_move(&self, digit: u32) -> Self {
+            Guess(self.0.iter().copied().chain([digit]).collect())
+        }
+
+        fn is_terminal_state(&self) -> Option<()> {
+            (self.0.len() == HIDDEN.len()).then_some(())
+        }
+
+        fn terminal_reward(&self, _: &()) -> f64 {
+            self.0.iter().zip(HIDDEN).filter(|&(&a, b)| a == b).count() as f64
+        }
+    }
+
+    impl MoveCode for Guess {
+        fn move_code(&self, digit: u32) -> u64 {
+            (self.0.len() * 4) as u64 + digit as u64
+        }
+    }
+
+    #[test]
+    fn nrpa_finds_the_hidden_digits() {
+        let best = Nrpa::<TestRng>::default()
+            .level(2)
+            .iterations(20)
+            .run(Guess(Vec::new()));
+        assert_eq!(best.moves, HIDDEN);
+        assert_eq!(best.score, HIDDEN.len() as f64);
+    }
+}
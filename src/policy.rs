@@ -0,0 +1,281 @@
+use crate::{default_exploration_constant, rng::Rng};
+
+/// Statistics of a node as seen by a selection policy.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub visits: u32,
+    /// sum of the rewards backpropagated through this node
+    pub reward: f64,
+    /// sum of the squared rewards
+    pub reward_sq: f64,
+    /// prior probability of the move leading to this node
+    pub prior: f64,
+    /// all-moves-as-first visits, only gathered when `SelectionPolicy::uses_amaf` is true
+    pub amaf_visits: u32,
+    /// sum of the all-moves-as-first rewards
+    pub amaf_reward: f64,
+    /// for ISMCTS, the visits of the parent in which this child's move was legal, which replace
+    /// the visits of the parent (see `Stats::parent_visits`)
+    pub available: Option<u32>,
+}
+
+impl Stats {
+    /// Average reward of this node.
+    pub fn mean(&self) -> f64 {
+        self.reward / self.visits as f64
+    }
+
+    /// Average all-moves-as-first reward of this node, 0 if it has none.
+    pub fn amaf_mean(&self) -> f64 {
+        if self.amaf_visits == 0 {
+            0.0
+        } else {
+            self.amaf_reward / self.amaf_visits as f64
+        }
+    }
+
+    /// Visits of `parent` to weigh the exploration of this child with: its availability for
+    /// ISMCTS, the visits of the parent otherwise.
+    pub fn parent_visits(&self, parent: &Stats) -> u32 {
+        self.available.unwrap_or(parent.visits)
+    }
+
+    /// Variance of the rewards of this node.
+    pub fn variance(&self) -> f64 {
+        let mean = self.mean();
+        (self.reward_sq / self.visits as f64 - mean * mean).max(0.0)
+    }
+}
+
+/// Implement this to control how the tree is descended during selection.
+pub trait SelectionPolicy: Clone + Send + 'static {
+    /// Score a child of `parent`, the child with the highest score is descended into.
+    fn score(&self, parent: &Stats, child: &Stats) -> f64;
+
+    /// Pick one of `children` to descend into and return its position in the slice.
+    fn choose<R: Rng>(&self, parent: &Stats, children: &[Stats], rng: &mut R) -> usize {
+        let _ = rng;
+        argmax(children.iter().map(|c| self.score(parent, c)))
+    }
+
+    /// Whether the tree should gather all-moves-as-first statistics for this policy.
+    fn uses_amaf(&self) -> bool {
+        false
+    }
+}
+
+/// position of the highest value, the first one wins ties
+pub(crate) fn argmax(values: impl Iterator<Item = f64>) -> usize {
+    values
+        .enumerate()
+        .fold((0, f64::NEG_INFINITY), |best, (idx, v)| {
+            if v > best.1 {
+                (idx, v)
+            } else {
+                best
+            }
+        })
+        .0
+}
+
+/// The classic UCB1 bound: `mean + c * sqrt(ln(N) / n)`.
+#[derive(Clone, Copy, Debug)]
+pub struct Ucb1 {
+    pub exploration: f64,
+}
+
+impl Ucb1 {
+    pub fn new(exploration: f64) -> Self {
+        Self { exploration }
+    }
+}
+
+impl Default for Ucb1 {
+    fn default() -> Self {
+        Self::new(default_exploration_constant())
+    }
+}
+
+impl SelectionPolicy for Ucb1 {
+    fn score(&self, parent: &Stats, child: &Stats) -> f64 {
+        let exploration = ((child.parent_visits(parent) as f64).ln() / child.visits as f64).sqrt();
+        child.mean() + self.exploration * exploration
+    }
+}
+
+/// UCB1-Tuned, which bounds the exploration term by the observed variance of the child.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Ucb1Tuned;
+
+impl SelectionPolicy for Ucb1Tuned {
+    fn score(&self, parent: &Stats, child: &Stats) -> f64 {
+        let log_ratio = (child.parent_visits(parent) as f64).ln() / child.visits as f64;
+        let variance_bound = child.variance() + (2.0 * log_ratio).sqrt();
+        child.mean() + (log_ratio * variance_bound.min(0.25)).sqrt()
+    }
+}
+
+/// UCB-V (Audibert et al.), a variance aware bound for rewards in `[0, 1]`.
+#[derive(Clone, Copy, Debug)]
+pub struct UcbV {
+    /// scale of the exploration function `zeta * ln(N)`
+    pub zeta: f64,
+    /// weight of the range term
+    pub c: f64,
+}
+
+impl Default for UcbV {
+    fn default() -> Self {
+        Self { zeta: 1.2, c: 1.0 }
+    }
+}
+
+impl SelectionPolicy for UcbV {
+    fn score(&self, parent: &Stats, child: &Stats) -> f64 {
+        let exploration = self.zeta * (child.parent_visits(parent) as f64).ln();
+        let n = child.visits as f64;
+        child.mean()
+            + (2.0 * child.variance() * exploration / n).sqrt()
+            + self.c * 3.0 * exploration / n
+    }
+}
+
+/// PUCT as used by AlphaZero: `mean + c * prior * sqrt(N) / (1 + n)`, see `GameState::move_priors`.
+#[derive(Clone, Copy, Debug)]
+pub struct Puct {
+    pub exploration: f64,
+}
+
+impl Puct {
+    pub fn new(exploration: f64) -> Self {
+        Self { exploration }
+    }
+}
+
+impl Default for Puct {
+    fn default() -> Self {
+        Self::new(default_exploration_constant())
+    }
+}
+
+impl SelectionPolicy for Puct {
+    fn score(&self, parent: &Stats, child: &Stats) -> f64 {
+        let exploration = (child.parent_visits(parent) as f64).sqrt() / (1 + child.visits) as f64;
+        child.mean() + self.exploration * child.prior * exploration
+    }
+}
+
+/// SP-MCTS (Schadd et al.) for single player games, see `MCTS::single_player`: UCB1 plus the
+/// possible deviation of a child, `sqrt(variance + d / n)`, so that children with rare high
+/// scores keep being searched. The defaults are those of the paper for SameGame scores.
+#[derive(Clone, Copy, Debug)]
+pub struct SpMcts {
+    pub exploration: f64,
+    /// added to the variance, high for children visited only a few times
+    pub d: f64,
+}
+
+impl Default for SpMcts {
+    fn default() -> Self {
+        Self {
+            exploration: 0.5,
+            d: 10000.0,
+        }
+    }
+}
+
+impl SelectionPolicy for SpMcts {
+    fn score(&self, parent: &Stats, child: &Stats) -> f64 {
+        let n = child.visits as f64;
+        let exploration = ((child.parent_visits(parent) as f64).ln() / n).sqrt();
+        let deviation = (child.variance() + self.d / n).sqrt();
+        child.mean() + self.exploration * exploration + deviation
+    }
+}
+
+/// How much weight `Rave` gives to the all-moves-as-first value over the regular value.
+#[derive(Clone, Copy, Debug)]
+pub enum RaveSchedule {
+    /// `beta = sqrt(k / (3n + k))`, where `k` is the number of visits at which both values are
+    /// weighted equally.
+    Equivalence(f64),
+    /// Minimum mean squared error schedule `beta = ñ / (n + ñ + 4 b² n ñ)`, where `b` is the
+    /// assumed bias of the all-moves-as-first value.
+    MinimumMse(f64),
+}
+
+impl RaveSchedule {
+    /// weight of the all-moves-as-first value for these statistics
+    fn beta(&self, stats: &Stats) -> f64 {
+        if stats.amaf_visits == 0 {
+            return 0.0;
+        }
+        let n = stats.visits as f64;
+        match *self {
+            RaveSchedule::Equivalence(k) => (k / (3.0 * n + k)).sqrt(),
+            RaveSchedule::MinimumMse(b) => {
+                let amaf_n = stats.amaf_visits as f64;
+                amaf_n / (n + amaf_n + 4.0 * b * b * n * amaf_n)
+            }
+        }
+    }
+}
+
+/// Rapid Action Value Estimation, blends the all-moves-as-first value of a child with its
+/// regular value according to `schedule` and adds the UCB1 exploration term.
+///
+/// Moves are compared with `Eq` to gather all-moves-as-first statistics, so a move should
+/// include the player making it if that matters for the game.
+#[derive(Clone, Copy, Debug)]
+pub struct Rave {
+    pub exploration: f64,
+    pub schedule: RaveSchedule,
+}
+
+impl Default for Rave {
+    fn default() -> Self {
+        Self {
+            exploration: default_exploration_constant(),
+            schedule: RaveSchedule::Equivalence(1000.0),
+        }
+    }
+}
+
+impl SelectionPolicy for Rave {
+    fn score(&self, parent: &Stats, child: &Stats) -> f64 {
+        let beta = self.schedule.beta(child);
+        let value = (1.0 - beta) * child.mean() + beta * child.amaf_mean();
+        let exploration = ((child.parent_visits(parent) as f64).ln() / child.visits as f64).sqrt();
+        value + self.exploration * exploration
+    }
+
+    fn uses_amaf(&self) -> bool {
+        true
+    }
+}
+
+/// Descend into the child with the best average reward, or a random child with probability `epsilon`.
+#[derive(Clone, Copy, Debug)]
+pub struct EpsilonGreedy {
+    pub epsilon: f64,
+}
+
+impl Default for EpsilonGreedy {
+    fn default() -> Self {
+        Self { epsilon: 0.1 }
+    }
+}
+
+impl SelectionPolicy for EpsilonGreedy {
+    fn score(&self, _parent: &Stats, child: &Stats) -> f64 {
+        child.mean()
+    }
+
+    fn choose<R: Rng>(&self, parent: &Stats, children: &[Stats], rng: &mut R) -> usize {
+        if rng.gen_f64() < self.epsilon {
+            rng.gen_range(0..children.len())
+        } else {
+            argmax(children.iter().map(|c| self.score(parent, c)))
+        }
+    }
+}
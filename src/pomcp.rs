@@ -0,0 +1,459 @@
+//! POMCP (Silver and Veness, 2010): Monte Carlo planning in partially observable environments
+//! through a generative model. The search tree is a `Tree` in observation mode (see
+//! `Tree::observations`): below every history, the node of an action draws the next state, the
+//! observation and the reward from the model, and every observation received gets its own
+//! history, which keeps the particles simulated through it as its belief. Actions are selected
+//! with any `SelectionPolicy` of the crate.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use crate::{
+    evaluator::{Outcome, RandomPlayout},
+    policy::{SelectionPolicy, Ucb1},
+    rng::{Rng, RngProvider},
+    GameState, Node, Tree,
+};
+
+/// Generative model of a partially observable environment, the only access POMCP needs to it.
+pub trait GenerativeModel {
+    type State: Clone;
+    type Action: Clone + Copy + Eq;
+    type Observation: Eq + Hash;
+
+    /// Actions available in `state`. The actions of a history are listed from the first state
+    /// simulated through it.
+    fn actions(&self, state: &Self::State) -> Vec<Self::Action>;
+
+    /// Sample the next state, the observation received and the reward of taking `action` in
+    /// `state`.
+    fn step<R: Rng>(
+        &self,
+        state: &Self::State,
+        action: Self::Action,
+        rng: &mut R,
+    ) -> (Self::State, Self::Observation, f64);
+
+    /// Whether no more rewards can be collected from `state`.
+    fn is_terminal(&self, state: &Self::State) -> bool {
+        let _ = state;
+        false
+    }
+
+    /// Action of the rollout policy used below the tree, defaults to a random action.
+    fn rollout_action<R: Rng>(&self, state: &Self::State, rng: &mut R) -> Self::Action {
+        let actions = self.actions(state);
+        actions[rng.gen_range(0..actions.len())]
+    }
+}
+
+/// A state of the model simulated from the root of the search, as a `GameState` of the tree.
+/// Taking an action leads to a chance state, pending until the model is stepped by drawing its
+/// only outcome. A terminal state reports the discounted return of the whole simulation, which
+/// every node turns into the return collected from its own state.
+struct Particle<M: GenerativeModel> {
+    model: Arc<M>,
+    state: M::State,
+    /// the action taken, until the model is stepped
+    action: Option<M::Action>,
+    /// key of the observation received on reaching the state
+    observation: Option<u64>,
+    depth: usize,
+    /// discounted sum of the rewards collected since the root
+    rewards: f64,
+    /// discount of the next reward
+    weight: f64,
+    discount: f64,
+    max_depth: usize,
+}
+
+impl<M: GenerativeModel> Clone for Particle<M> {
+    fn clone(&self) -> Self {
+        Self {
+            model: self.model.clone(),
+            state: self.state.clone(),
+            action: self.action,
+            observation: self.observation,
+            depth: self.depth,
+            rewards: self.rewards,
+            weight: self.weight,
+            discount: self.discount,
+            max_depth: self.max_depth,
+        }
+    }
+}
+
+impl<M: GenerativeModel> GameState for Particle<M> {
+    /// `Some` action, or `None` to step the model after it
+    type Move = Option<M::Action>;
+    /// bits of the return of the simulation
+    type UserData = u64;
+
+    fn all_moves(&self) -> Vec<Self::Move> {
+        match self.action {
+            Some(_) => vec![None],
+            None => self
+                .model
+                .actions(&self.state)
+                .into_iter()
+                .map(Some)
+                .collect(),
+        }
+    }
+
+    fn random_move<R: Rng>(&self, rng: &mut R) -> Option<Self::Move> {
+        Some(
+            self.action
+                .is_none()
+                .then(|| self.model.rollout_action(&self.state, rng)),
+        )
+    }
+
+    fn apply_move(&self, _: Self::Move) -> Self {
+        panic!("the model is stepped with apply_move_with_rng");
+    }
+
+    fn apply_move_with_rng<R: Rng>(&self, m: Self::Move, rng: &mut R) -> Self {
+        let mut next = self.clone();
+        let Some(action) = self.action else {
+            next.action = m;
+            return next;
+        };
+        let (state, observation, reward) = self.model.step(&self.state, action, rng);
+        next.state = state;
+        next.action = None;
+        next.observation = Some(observation_key(&observation));
+        next.depth += 1;
+        next.rewards += self.weight * reward;
+        next.weight *= self.discount;
+        next
+    }
+
+    fn is_terminal_state(&self) -> Option<u64> {
+        let terminal = self.depth >= self.max_depth || self.model.is_terminal(&self.state);
+        (self.action.is_none() && terminal).then_some(self.rewards.to_bits())
+    }
+
+    fn terminal_reward(&self, rewards: &u64) -> f64 {
+        // a pending action shares the rewards of its history, and is valued with the reward of
+        // its step
+        if self.weight == 0.0 {
+            return 0.0;
+        }
+        (f64::from_bits(*rewards) - self.rewards) / self.weight
+    }
+
+    fn chance_outcomes(&self) -> Option<Vec<(Self::Move, f64)>> {
+        self.action.map(|_| vec![(None, 1.0)])
+    }
+
+    fn observation(&self) -> Option<u64> {
+        self.observation
+    }
+}
+
+fn observation_key<O: Hash>(observation: &O) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    observation.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// POMCP planner keeping its tree and belief between real steps, see `update`. Returns are not
+/// limited to `[0, 1]`, the exploration constant of the selection policy should be scaled to
+/// their range.
+pub struct Pomcp<M, R, P = Ucb1>
+where
+    M: GenerativeModel,
+    R: RngProvider,
+    P: SelectionPolicy,
+{
+    model: Arc<M>,
+    discount: f64,
+    max_depth: usize,
+    num_particles: usize,
+    /// empty until the first simulation, whose particle becomes the state of the root
+    tree: Tree<Particle<M>, P, RandomPlayout>,
+    /// the particles of the root
+    belief: Vec<M::State>,
+    rng: R,
+}
+
+impl<M, R, P> Pomcp<M, R, P>
+where
+    M: GenerativeModel,
+    R: RngProvider,
+    P: SelectionPolicy,
+{
+    /// A planner starting from `belief`, particles sampled from the initial belief.
+    pub fn new(model: M, belief: Vec<M::State>) -> Self
+    where
+        P: Default,
+    {
+        Self {
+            model: Arc::new(model),
+            discount: 0.95,
+            max_depth: 100,
+            num_particles: 1000,
+            tree: Tree::new(P::default(), RandomPlayout).observations(true),
+            belief,
+            rng: R::init(),
+        }
+    }
+
+    /// Use a different selection policy to choose actions.
+    pub fn policy<Q: SelectionPolicy>(self, policy: Q) -> Pomcp<M, R, Q> {
+        Pomcp {
+            model: self.model,
+            discount: self.discount,
+            max_depth: self.max_depth,
+            num_particles: self.num_particles,
+            tree: Tree::new(policy, RandomPlayout).observations(true),
+            belief: self.belief,
+            rng: self.rng,
+        }
+    }
+
+    /// Discount factor of the rewards, 0.95 by default.
+    pub fn discount(mut self, discount: f64) -> Self {
+        self.discount = discount;
+        self
+    }
+
+    /// Number of steps simulated from the root, in the tree and in rollouts. 100 by default.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Number of particles kept in each belief, 1000 by default.
+    pub fn num_particles(mut self, num_particles: usize) -> Self {
+        self.num_particles = num_particles;
+        self
+    }
+
+    /// The particles of the current belief.
+    pub fn belief(&self) -> &[M::State] {
+        &self.belief
+    }
+
+    pub fn run_with_end_condition(
+        &mut self,
+        end_condition: impl Fn(u32) -> bool,
+    ) -> Option<M::Action> {
+        let mut iterations = 0;
+        while !end_condition(iterations) && !self.belief.is_empty() {
+            self.simulate();
+            iterations += 1;
+        }
+        self.best_action()
+    }
+
+    #[cfg(feature = "chrono")]
+    pub fn run_with_duration(&mut self, duration: chrono::TimeDelta) -> Option<M::Action> {
+        let end_time = chrono::Utc::now() + duration;
+
+        self.run_with_end_condition(move |_| chrono::Utc::now() >= end_time)
+    }
+
+    pub fn run_with_iterations(&mut self, num_iterations: u32) -> Option<M::Action> {
+        self.run_with_end_condition(move |iters| iters >= num_iterations)
+    }
+
+    /// The most visited action of the root, `None` if nothing was searched.
+    pub fn best_action(&self) -> Option<M::Action> {
+        let root = self.tree.nodes.first()?;
+        root.children
+            .iter()
+            .filter(|e| e.visits.get() > 1)
+            .max_by_key(|e| e.visits.get())
+            .and_then(|e| e.mv)
+    }
+
+    /// Take a real step: `action` was taken and `observation` received. The tree is re-rooted at
+    /// the matching history, whose particles are topped up by rejection sampling from the
+    /// previous belief. Returns `false` if no particle is consistent with the observation, the
+    /// belief is then empty and nothing can be searched until a new planner is created.
+    pub fn update(&mut self, action: M::Action, observation: M::Observation) -> bool {
+        let key = observation_key(&observation);
+        let tree = &self.tree;
+        let history = tree
+            .nodes
+            .first()
+            .and_then(|root| root.children.iter().find(|e| e.mv == Some(action)))
+            .and_then(|e| {
+                tree[e.child]
+                    .children
+                    .iter()
+                    .find(|o| tree[o.child].state.observation() == Some(key))
+            })
+            .map(|o| o.child);
+        let previous = std::mem::take(&mut self.belief);
+        if let Some(history) = history {
+            let particles = std::mem::take(&mut self.tree[history].belief);
+            self.belief = particles.into_iter().map(|p| p.state).collect();
+        }
+
+        let mut attempts = 0;
+        while self.belief.len() < self.num_particles
+            && attempts < 10 * self.num_particles
+            && !previous.is_empty()
+        {
+            let state = &previous[self.rng.gen_range(0..previous.len())];
+            let (next, o, _) = self.model.step(state, action, &mut self.rng);
+            if o == observation {
+                self.belief.push(next);
+            }
+            attempts += 1;
+        }
+
+        if self.belief.is_empty() || self.tree.nodes.is_empty() {
+            self.tree.nodes.clear();
+        } else {
+            let mut root = self.particle(self.belief[0].clone());
+            root.observation = Some(key);
+            self.tree.advance_to(Some(action), root);
+        }
+        !self.belief.is_empty()
+    }
+
+    /// A particle of the root for `state`.
+    fn particle(&self, state: M::State) -> Particle<M> {
+        Particle {
+            model: self.model.clone(),
+            state,
+            action: None,
+            observation: None,
+            depth: 0,
+            rewards: 0.0,
+            weight: 1.0,
+            discount: self.discount,
+            max_depth: self.max_depth,
+        }
+    }
+
+    /// Simulate one trajectory from a particle of the root belief, keeping the states it reaches
+    /// in the beliefs of the histories it goes through.
+    fn simulate(&mut self) {
+        let state = self.belief[self.rng.gen_range(0..self.belief.len())].clone();
+        let root = self.particle(state);
+        match self.tree.nodes.first_mut() {
+            Some(node) => node.state = root,
+            None => {
+                self.tree.add_node(Node::new(root));
+            }
+        }
+
+        let mut path = self.tree.select(&mut self.rng);
+        let leaf = *path.last().unwrap();
+        let mut moves = Vec::new();
+        let result = match self.tree[leaf].state.is_terminal_state() {
+            Some(rewards) => Outcome::Terminal(rewards),
+            None => {
+                let children = self.tree.expand(leaf, &mut self.rng);
+                if !children.is_empty() {
+                    let child = children[self.rng.gen_range(0..children.len())];
+                    self.tree.resimulate(leaf, child, &mut self.rng);
+                    path.push(child);
+                }
+                let leaf = *path.last().unwrap();
+                self.tree.random_playout(leaf, &mut self.rng, &mut moves)
+            }
+        };
+
+        for &idx in &path[1..] {
+            let node = &mut self.tree[idx];
+            if !node.chance && node.belief.len() < self.num_particles {
+                let state = node.state.clone();
+                node.belief.push(state);
+            }
+        }
+        self.tree.backpropagate(&path, result, &moves);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GenerativeModel, Pomcp};
+    use crate::{
+        policy::Ucb1,
+        rng::{Rng, RngProvider},
+        testing::TestRng,
+    };
+
+    const LISTEN: usize = 0;
+    const OPEN_LEFT: usize = 1;
+    const OPEN_RIGHT: usize = 2;
+    const HEAR_LEFT: usize = 0;
+
+    /// The tiger problem: a tiger is behind the left or the right door. Listening costs 1 and
+    /// hears the tiger behind the right door 85% of the time, opening a door ends the problem
+    /// with 10 for the other door and -100 for the door of the tiger.
+    struct Tiger;
+
+    /// Whether the tiger is behind the left door, and whether a door was opened.
+    type State = (bool, bool);
+
+    impl GenerativeModel for Tiger {
+        type State = State;
+        type Action = usize;
+        type Observation = usize;
+
+        fn actions(&self, _: &State) -> Vec<usize> {
+            vec![LISTEN, OPEN_LEFT, OPEN_RIGHT]
+        }
+
+        fn step<R: Rng>(
+            &self,
+            &(left, _): &State,
+            action: usize,
+            rng: &mut R,
+        ) -> (State, usize, f64) {
+            match action {
+                LISTEN => {
+                    let correct = rng.gen_range(0..100) < 85;
+                    let observation = if left == correct { HEAR_LEFT } else { 1 };
+                    ((left, false), observation, -1.0)
+                }
+                _ => {
+                    let reward = if (action == OPEN_LEFT) == left {
+                        -100.0
+                    } else {
+                        10.0
+                    };
+                    ((left, true), 2, reward)
+                }
+            }
+        }
+
+        fn is_terminal(&self, &(_, opened): &State) -> bool {
+            opened
+        }
+    }
+
+    fn planner() -> Pomcp<Tiger, TestRng> {
+        let mut rng = TestRng::init();
+        let belief = (0..1000)
+            .map(|_| (rng.gen_range(0..2) == 0, false))
+            .collect();
+        Pomcp::<Tiger, TestRng>::new(Tiger, belief).policy(Ucb1::new(100.0))
+    }
+
+    #[test]
+    fn tiger_is_listened_to_first() {
+        let mut pomcp = planner();
+        assert_eq!(pomcp.run_with_iterations(20000), Some(LISTEN));
+    }
+
+    #[test]
+    fn listening_narrows_the_belief() {
+        let mut pomcp = planner();
+        pomcp.run_with_iterations(5000);
+        assert!(pomcp.update(LISTEN, HEAR_LEFT));
+        let belief = pomcp.belief();
+        let left = belief.iter().filter(|s| s.0).count() as f64 / belief.len() as f64;
+        assert!((0.75..0.95).contains(&left), "{left}");
+    }
+}
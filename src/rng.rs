@@ -3,11 +3,36 @@ use std::ops::Range;
 /// Implement this for any custom random number generator
 pub trait Rng: Send + Sync + 'static {
     fn gen_range(&mut self, bounds: Range<usize>) -> usize;
+
+    /// Returns a uniformly distributed number in `[0, 1)`.
+    fn gen_f64(&mut self) -> f64 {
+        let hi = self.gen_range(0..1 << 26) as f64;
+        let lo = self.gen_range(0..1 << 27) as f64;
+        (hi * (1u64 << 27) as f64 + lo) / (1u64 << 53) as f64
+    }
 }
 pub trait RngProvider: Rng {
     fn init() -> Self;
 }
 
+/// Draw an index with probability proportional to its weight.
+pub(crate) fn weighted_index<R: Rng>(
+    weights: impl Iterator<Item = f64> + Clone,
+    rng: &mut R,
+) -> usize {
+    let total = weights.clone().sum::<f64>();
+    let mut x = rng.gen_f64() * total;
+    let mut last = 0;
+    for (i, w) in weights.enumerate() {
+        if x < w {
+            return i;
+        }
+        x -= w;
+        last = i;
+    }
+    last
+}
+
 #[cfg(feature = "nanorand")]
 mod default_rng {
     use nanorand::WyRand;
@@ -26,6 +51,11 @@ mod default_rng {
             use nanorand::Rng;
             self.0.generate_range(bounds)
         }
+
+        fn gen_f64(&mut self) -> f64 {
+            use nanorand::Rng;
+            (self.0.generate::<u64>() >> 11) as f64 / (1u64 << 53) as f64
+        }
     }
 }
 
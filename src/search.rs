@@ -0,0 +1,469 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex, RwLock, RwLockReadGuard,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::{
+    batch::BatchQueue,
+    evaluator::{self, Evaluator, Outcome, RandomPlayout},
+    policy::{SelectionPolicy, Ucb1},
+    rng::{Rng, RngProvider},
+    BestResultHandle, GameState, Line, Parallelism, RootChild, ThreadResult, Tree, MCTS,
+};
+
+type Playout<T, X> = (
+    Outcome<<T as GameState>::UserData, X>,
+    Vec<<T as GameState>::Move>,
+);
+
+/// A tree searched by a single thread (root parallelism) or by every thread (tree parallelism).
+/// Threads select and backpropagate under the read lock, updating the statistics of the nodes
+/// atomically, and only take the write lock to add nodes.
+pub(crate) struct SharedTree<T: GameState, P: SelectionPolicy, E: Evaluator<T>> {
+    tree: RwLock<Tree<T, P, E>>,
+    /// threads still searching this tree
+    running: AtomicUsize,
+}
+
+impl<T: GameState, P: SelectionPolicy, E: Evaluator<T>> SharedTree<T, P, E> {
+    pub fn new(tree: Tree<T, P, E>) -> Self {
+        Self {
+            tree: RwLock::new(tree),
+            running: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// A search that keeps its trees between runs, created with `MCTS::search`. Once a run is
+/// joined, `advance` plays a move on every tree so that the next run starts from the work
+/// already done below that move.
+pub struct Search<T, R, P = Ucb1, E = RandomPlayout>
+where
+    T: GameState,
+    R: RngProvider,
+    P: SelectionPolicy,
+    E: Evaluator<T>,
+{
+    mcts: MCTS<R, P, E>,
+    state: T,
+    /// one tree per thread with root parallelism, a single tree otherwise
+    trees: Vec<Arc<SharedTree<T, P, E>>>,
+}
+
+impl<T, R, P, E> Search<T, R, P, E>
+where
+    T: GameState + Send + Sync + 'static,
+    T::Move: Send + Sync,
+    T::UserData: Send,
+    R: RngProvider,
+    P: SelectionPolicy + Sync,
+    E: Evaluator<T> + Sync,
+{
+    pub(crate) fn new(mcts: MCTS<R, P, E>, state: T) -> Self {
+        let ntrees = match mcts.parallelism {
+            Parallelism::Root => mcts.num_threads,
+            Parallelism::Tree | Parallelism::Leaf => 1,
+        };
+        let trees = (0..ntrees)
+            .map(|_| Arc::new(SharedTree::new(mcts.new_tree(state.clone()))))
+            .collect();
+        Self { mcts, state, trees }
+    }
+
+    /// The state at the root of the search.
+    pub fn state(&self) -> &T {
+        &self.state
+    }
+
+    /// Play `m`, whoever's move it is, re-rooting every tree at the child reached with it. The
+    /// rest of each tree is dropped. Panics if a run has not been joined yet.
+    pub fn advance(&mut self, m: T::Move) {
+        let state = self.state.apply_move(m);
+        self.advance_to(m, state);
+    }
+
+    /// Same as `advance` with `state` the state actually reached with `m`, such as the outcome
+    /// observed after a random move in open-loop mode (see `Tree::advance_to`).
+    pub fn advance_to(&mut self, m: T::Move, state: T) {
+        for tree in &mut self.trees {
+            Arc::get_mut(tree)
+                .expect("advance called before the search was joined")
+                .tree
+                .get_mut()
+                .unwrap()
+                .advance_to(m, state.clone());
+        }
+        self.state = state;
+    }
+
+    /// The principal variation: the most visited line from the root, up to `depth` moves. The
+    /// visits of the trees of root parallelism are summed by move.
+    pub fn principal_variation(&self, depth: usize) -> Vec<T::Move> {
+        let trees = self.lock_trees();
+        let trees = trees.iter().map(|t| &**t).collect::<Vec<_>>();
+        let state = trees[0][0].state.clone();
+        crate::principal_variation(&trees, vec![Some(0); trees.len()], state, depth)
+    }
+
+    /// The `k` most visited root moves, each with its statistics and followed by its principal
+    /// variation, for lines of up to `depth` moves. Moves that were never visited are left out.
+    pub fn multi_pv(&self, k: usize, depth: usize) -> Vec<Line<T::Move>> {
+        let trees = self.lock_trees();
+        let trees = trees.iter().map(|t| &**t).collect::<Vec<_>>();
+        crate::multi_pv(&trees, k, depth)
+    }
+
+    fn lock_trees(&self) -> Vec<RwLockReadGuard<'_, Tree<T, P, E>>> {
+        self.trees.iter().map(|t| t.tree.read().unwrap()).collect()
+    }
+
+    /// Search until `end_condition` is met, starting from the trees of the previous runs.
+    /// Panics if the previous run has not been joined yet.
+    pub fn run_with_end_condition(
+        &self,
+        end_condition: impl Fn(usize, u32) -> bool + Send + Copy + 'static,
+    ) -> BestResultHandle<T> {
+        let mcts = &self.mcts;
+        // with leaf parallelism a single thread searches the tree, the others only run playouts
+        let (nthreads, pool_size) = match mcts.parallelism {
+            Parallelism::Leaf => (1, mcts.num_threads),
+            _ => (mcts.num_threads, 0),
+        };
+        let leaves_per_batch = mcts.batch_size.div_ceil(nthreads);
+        let queue = (mcts.batch_size > 1)
+            .then(|| Arc::new(BatchQueue::new(mcts.batch_size, mcts.batch_timeout)));
+        for tree in &self.trees {
+            assert_eq!(
+                Arc::strong_count(tree),
+                1,
+                "run started before the previous run was joined"
+            );
+            tree.running
+                .store(nthreads / self.trees.len(), Ordering::Release);
+        }
+
+        let threads = (0..nthreads)
+            .map(|i| {
+                let tree = self.trees[i % self.trees.len()].clone();
+                let evaluator = mcts.evaluator.clone();
+                let queue = queue.clone();
+                let settings = SearchSettings {
+                    nthreads,
+                    leaves_per_batch,
+                    single_player: mcts.single_player,
+                    end_condition,
+                };
+                let mut rng = R::init();
+                thread::spawn(move || {
+                    let pool = (pool_size > 0).then(|| {
+                        PlayoutPool::new::<E, R>(pool_size, &evaluator, settings.single_player)
+                    });
+                    search(
+                        &tree,
+                        &evaluator,
+                        queue.as_deref(),
+                        pool.as_ref(),
+                        &settings,
+                        &mut rng,
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+
+        BestResultHandle {
+            threads,
+            selection: mcts.final_move_selection,
+        }
+    }
+
+    #[cfg(feature = "chrono")]
+    pub fn run_with_duration(&self, duration: chrono::TimeDelta) -> BestResultHandle<T> {
+        let end_time = chrono::Utc::now() + duration;
+
+        self.run_with_end_condition(move |_, _| chrono::Utc::now() >= end_time)
+    }
+
+    pub fn run_with_iterations(&self, num_iterations: u32) -> BestResultHandle<T> {
+        self.run_with_end_condition(move |nthreads, iters| {
+            iters >= num_iterations / nthreads as u32
+        })
+    }
+}
+
+/// Worker threads playing out leaves for leaf parallelism.
+pub(crate) struct PlayoutPool<T: GameState, X> {
+    jobs: Option<Sender<T>>,
+    results: Receiver<Playout<T, X>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<T, X> PlayoutPool<T, X>
+where
+    T: GameState + Send + 'static,
+    T::Move: Send,
+    T::UserData: Send,
+    X: Send + 'static,
+{
+    /// Start `size` workers, scoring terminal states for single-player searches with
+    /// `single_player`.
+    pub fn new<E, R>(size: usize, evaluator: &E, single_player: bool) -> Self
+    where
+        E: Evaluator<T, Estimate = X>,
+        R: RngProvider,
+    {
+        let (jobs, job_receiver) = mpsc::channel::<T>();
+        let (result_sender, results) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let workers = (0..size)
+            .map(|_| {
+                let job_receiver = job_receiver.clone();
+                let result_sender = result_sender.clone();
+                let evaluator = evaluator.clone();
+                let mut rng = R::init();
+                thread::spawn(move || loop {
+                    let job = job_receiver.lock().unwrap().recv();
+                    let Ok(state) = job else {
+                        break;
+                    };
+                    let mut moves = Vec::new();
+                    let result =
+                        evaluator::playout(state, &evaluator, single_player, &mut rng, &mut moves);
+                    if result_sender.send((result, moves)).is_err() {
+                        break;
+                    }
+                })
+            })
+            .collect();
+
+        Self {
+            jobs: Some(jobs),
+            results,
+            workers,
+        }
+    }
+
+    /// Play `state` out once on every worker and wait for all the results.
+    fn playouts(&self, state: &T) -> Vec<Playout<T, X>> {
+        let jobs = self.jobs.as_ref().unwrap();
+        for _ in &self.workers {
+            jobs.send(state.clone()).unwrap();
+        }
+        self.workers
+            .iter()
+            .map(|_| self.results.recv().unwrap())
+            .collect()
+    }
+}
+
+impl<T: GameState, X> Drop for PlayoutPool<T, X> {
+    fn drop(&mut self) {
+        // closing the job channel stops the workers
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Settings shared by every search thread.
+pub(crate) struct SearchSettings<F> {
+    pub nthreads: usize,
+    /// descents per thread before their leaves are evaluated
+    pub leaves_per_batch: usize,
+    /// whether playouts return the score of their terminal state, see `MCTS::single_player`
+    pub single_player: bool,
+    pub end_condition: F,
+}
+
+/// Select a leaf, expand it and add a virtual loss on the path to the new child, which is
+/// returned with its state. Terminal states are backpropagated right away and return `None`.
+/// Closed-loop trees are selected under the read lock, by every thread at once, and only locked
+/// for writing to expand the leaf. Open-loop trees simulate their states again while selecting
+/// and stay locked for writing.
+fn descend<T, P, E, R>(tree: &RwLock<Tree<T, P, E>>, rng: &mut R) -> Option<(Vec<usize>, T)>
+where
+    T: GameState,
+    P: SelectionPolicy,
+    E: Evaluator<T>,
+    R: Rng,
+{
+    let selected = {
+        let tree = tree.read().unwrap();
+        (!tree.open_loop).then(|| tree.select_shared(rng))
+    };
+    let mut tree = tree.write().unwrap();
+    let (mut path, pending) = match selected {
+        Some(path) => {
+            let pending = path.len();
+            (path, pending)
+        }
+        None => (tree.select(rng), 0),
+    };
+    loop {
+        let leaf = *path.last().unwrap();
+        let terminal = tree[leaf].state.is_terminal_state();
+
+        // if terminal state, backprogagate it otherwise expand
+        if let Some(reward) = terminal {
+            tree.remove_virtual_loss(&path[..pending]);
+            tree.backpropagate(&path, Outcome::Terminal(reward), &[]);
+            return None;
+        }
+
+        let new_children = tree.expand(leaf, rng);
+        let child = if new_children.is_empty() || tree[leaf].is_chance() {
+            // progressive widening drew a move that already has a child, or another thread
+            // expanded the leaf first, keep descending instead, and outcomes of chance nodes are
+            // drawn by probability
+            tree.choose_child(leaf, rng)
+        } else {
+            new_children[rng.gen_range(0..new_children.len())]
+        };
+
+        // a child already on the path closes a cycle of transpositions, the leaf is played out
+        // instead
+        if !path.contains(&child) {
+            tree.resimulate(leaf, child, rng);
+            path.push(child);
+            if new_children.is_empty() {
+                tree.select_from(&mut path, rng);
+                continue;
+            }
+        }
+
+        // the nodes selected under the read lock already have theirs
+        tree.add_virtual_loss(&path[pending..]);
+        let state = tree[*path.last().unwrap()].state.clone();
+        return Some((path, state));
+    }
+}
+
+/// Search `shared` until the end condition is met. Leaves are evaluated without holding any lock,
+/// so that threads sharing the tree run their playouts in parallel, and their results are
+/// backpropagated under the read lock.
+pub(crate) fn search<T, P, E, R, F>(
+    shared: &SharedTree<T, P, E>,
+    evaluator: &E,
+    queue: Option<&BatchQueue<T, E::Estimate>>,
+    pool: Option<&PlayoutPool<T, E::Estimate>>,
+    settings: &SearchSettings<F>,
+    rng: &mut R,
+) -> ThreadResult<T>
+where
+    T: GameState + Send + 'static,
+    T::Move: Send,
+    T::UserData: Send,
+    P: SelectionPolicy,
+    E: Evaluator<T>,
+    R: Rng,
+    F: Fn(usize, u32) -> bool,
+{
+    let mut iterations = 0;
+    let mut finished = false;
+    while !finished {
+        let mut leaves = Vec::with_capacity(settings.leaves_per_batch);
+        let mut states = Vec::with_capacity(settings.leaves_per_batch);
+        while leaves.len() < settings.leaves_per_batch {
+            if let Some((path, state)) = descend(&shared.tree, rng) {
+                states.push(state);
+                leaves.push(path);
+            }
+
+            if (settings.end_condition)(settings.nthreads, iterations)
+                || shared.tree.read().unwrap().is_solved()
+            {
+                finished = true;
+                break;
+            }
+
+            iterations += 1;
+        }
+
+        let estimates = match queue {
+            Some(queue) => queue.evaluate(states.clone(), evaluator),
+            None => states.iter().map(|_| None).collect(),
+        };
+
+        let results = states
+            .into_iter()
+            .zip(estimates)
+            .map(|(state, estimate)| match (estimate, pool) {
+                (Some(estimate), _) => vec![(Outcome::Estimate(estimate), Vec::new())],
+                (None, Some(pool)) => pool.playouts(&state),
+                (None, None) => {
+                    let mut moves = Vec::new();
+                    let single_player = settings.single_player;
+                    let result =
+                        evaluator::playout(state, evaluator, single_player, rng, &mut moves);
+                    vec![(result, moves)]
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let tree = shared.tree.read().unwrap();
+        for (path, playouts) in leaves.into_iter().zip(results) {
+            tree.remove_virtual_loss(&path);
+            for (result, moves) in playouts {
+                tree.backpropagate(&path, result, &moves);
+            }
+        }
+    }
+
+    // the last thread to finish reports the statistics of the tree
+    let mut result = ThreadResult {
+        iterations,
+        proof: None,
+        root: Vec::new(),
+        best: None,
+    };
+    if shared.running.fetch_sub(1, Ordering::AcqRel) == 1 {
+        let tree = shared.tree.read().unwrap();
+        result.proof = tree[0].proof;
+        result.best = tree.best_trajectory();
+        result.root = tree
+            .edges(0)
+            .map(|(mv, idx, stats)| RootChild {
+                mv,
+                visits: stats.visits,
+                reward: stats.reward,
+                reward_sq: stats.reward_sq,
+                proof: tree[idx].proof,
+            })
+            .collect();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Nim, TestRng};
+
+    #[test]
+    fn threads_sharing_a_tree_keep_its_statistics_consistent() {
+        let mut search = MCTS::<TestRng>::default()
+            .num_threads(4)
+            .parallelism(Parallelism::Tree)
+            .search(Nim::new(10).with_transpositions());
+        let result = search.run_with_iterations(8000).join();
+        assert_eq!(result.best_move, 2);
+
+        let tree = Arc::get_mut(&mut search.trees[0])
+            .unwrap()
+            .tree
+            .get_mut()
+            .unwrap();
+        assert!(tree.nodes.iter().all(|n| n.vl.get() == 0));
+        let through_edges = tree[0]
+            .children
+            .iter()
+            .map(|e| e.visits.get() - 1)
+            .sum::<u32>();
+        assert_eq!(tree[0].n.get() - 1, through_edges);
+    }
+}
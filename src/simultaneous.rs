@@ -0,0 +1,480 @@
+//! Simultaneous move games, where every player picks a move without knowing the others'. Each
+//! node runs one bandit per player over the moves of that player alone (decoupled UCT, Exp3 or
+//! regret matching), and the result of a search is a mixed strategy per player instead of a
+//! single best move.
+
+use std::{collections::HashMap, marker::PhantomData};
+
+use crate::{
+    default_exploration_constant,
+    policy::argmax,
+    rng::{self, Rng, RngProvider},
+};
+
+/// A state of a game in which all players move at once.
+pub trait SimultaneousState: Clone {
+    type Move: Clone + Copy + Eq + Send;
+    type UserData: Eq + Send;
+
+    /// Number of players, 2 by default.
+    fn num_players(&self) -> usize {
+        2
+    }
+
+    /// Moves of `player` in this state. Every player needs at least one move in a non terminal
+    /// state, a player who does not act should get a single pass move.
+    fn player_moves(&self, player: usize) -> Vec<Self::Move>;
+
+    /// Apply the joint move, `moves[p]` being the move of player `p`.
+    fn apply_moves(&self, moves: &[Self::Move]) -> Self;
+
+    /// Determine if this is a terminal state. If so then return metadata about the state.
+    fn is_terminal_state(&self) -> Option<Self::UserData>;
+
+    /// Given metadata from a terminal state, the reward of every player indexed by player, in
+    /// `[0, 1]`.
+    fn terminal_rewards(&self, condition: &Self::UserData) -> Vec<f64>;
+}
+
+/// How each player of a node chooses their move, from their own statistics only.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bandit {
+    /// UCB1 on the mean reward of each move. The strategy is given by the visit counts.
+    DecoupledUct { exploration: f64 },
+    /// Exp3, exploring uniformly with probability `gamma`. The strategy is the average of the
+    /// sampling distributions, without the uniform exploration.
+    Exp3 { gamma: f64 },
+    /// Regret matching, exploring uniformly with probability `gamma`. The strategy is the average
+    /// of the regret matching distributions, which converges to a Nash equilibrium in two player
+    /// zero-sum games.
+    RegretMatching { gamma: f64 },
+}
+
+impl Default for Bandit {
+    fn default() -> Self {
+        Bandit::DecoupledUct {
+            exploration: default_exploration_constant(),
+        }
+    }
+}
+
+/// Statistics of one move of one player at a node.
+#[derive(Clone, Copy, Debug, Default)]
+struct Arm {
+    visits: u32,
+    reward: f64,
+    /// importance weighted sum of the rewards, for Exp3
+    estimate: f64,
+    /// cumulative regret of not always playing this move, for regret matching
+    regret: f64,
+    /// sum of the probabilities this move was chosen with
+    strategy: f64,
+}
+
+/// The bandit of one player at a node.
+struct Player<M> {
+    moves: Vec<M>,
+    arms: Vec<Arm>,
+}
+
+struct Node<T: SimultaneousState> {
+    state: T,
+    visits: u32,
+    /// one bandit per player, empty for terminal states
+    players: Vec<Player<T::Move>>,
+    /// children by the index of the move of every player
+    children: HashMap<Vec<usize>, usize>,
+}
+
+impl<T: SimultaneousState> Node<T> {
+    fn new(state: T) -> Self {
+        let players = if state.is_terminal_state().is_some() {
+            Vec::new()
+        } else {
+            (0..state.num_players())
+                .map(|p| {
+                    let moves = state.player_moves(p);
+                    let arms = vec![Arm::default(); moves.len()];
+                    Player { moves, arms }
+                })
+                .collect()
+        };
+        Self {
+            state,
+            visits: 0,
+            players,
+            children: HashMap::new(),
+        }
+    }
+}
+
+/// A mixed strategy: moves with the probability of playing them.
+#[derive(Clone, Debug)]
+pub struct MixedStrategy<M> {
+    pub moves: Vec<(M, f64)>,
+}
+
+impl<M: Copy> MixedStrategy<M> {
+    /// Draw a move by probability.
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> M {
+        self.moves[rng::weighted_index(self.moves.iter().map(|m| m.1), rng)].0
+    }
+
+    /// The most likely move.
+    pub fn most_likely(&self) -> M {
+        self.moves[argmax(self.moves.iter().map(|m| m.1))].0
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SimultaneousResult<M> {
+    pub iterations: u32,
+    /// the strategy of every player at the root, indexed by player
+    pub strategies: Vec<MixedStrategy<M>>,
+}
+
+/// Search of simultaneous move games, see `SimultaneousState`.
+pub struct SimultaneousMcts<R: RngProvider> {
+    bandit: Bandit,
+    rng_type: PhantomData<R>,
+}
+
+impl<R: RngProvider> Clone for SimultaneousMcts<R> {
+    fn clone(&self) -> Self {
+        Self {
+            bandit: self.bandit,
+            rng_type: PhantomData,
+        }
+    }
+}
+
+impl<R: RngProvider> Default for SimultaneousMcts<R> {
+    fn default() -> Self {
+        Self {
+            bandit: Bandit::default(),
+            rng_type: PhantomData,
+        }
+    }
+}
+
+impl<R: RngProvider> SimultaneousMcts<R> {
+    /// Set how the players choose their moves, decoupled UCT by default.
+    pub fn bandit(mut self, bandit: Bandit) -> Self {
+        self.bandit = bandit;
+        self
+    }
+
+    pub fn run_with_end_condition<T: SimultaneousState>(
+        &self,
+        state: T,
+        end_condition: impl Fn(u32) -> bool,
+    ) -> SimultaneousResult<T::Move> {
+        let mut tree = vec![Node::new(state)];
+        let mut rng = R::init();
+        let mut iterations = 0;
+        while !end_condition(iterations) && !tree[0].players.is_empty() {
+            self.iterate(&mut tree, &mut rng);
+            iterations += 1;
+        }
+
+        let strategies = tree[0]
+            .players
+            .iter()
+            .map(|player| self.strategy(player))
+            .collect();
+        SimultaneousResult {
+            iterations,
+            strategies,
+        }
+    }
+
+    #[cfg(feature = "chrono")]
+    pub fn run_with_duration<T: SimultaneousState>(
+        &self,
+        state: T,
+        duration: chrono::TimeDelta,
+    ) -> SimultaneousResult<T::Move> {
+        let end_time = chrono::Utc::now() + duration;
+
+        self.run_with_end_condition(state, move |_| chrono::Utc::now() >= end_time)
+    }
+
+    pub fn run_with_iterations<T: SimultaneousState>(
+        &self,
+        state: T,
+        num_iterations: u32,
+    ) -> SimultaneousResult<T::Move> {
+        self.run_with_end_condition(state, move |iters| iters >= num_iterations)
+    }
+
+    /// Descend from the root with a joint move drawn at every node, play the new leaf out at
+    /// random and update the bandit of every player on the path with their own reward.
+    fn iterate<T: SimultaneousState>(&self, tree: &mut Vec<Node<T>>, rng: &mut R) {
+        // nodes with the chosen move of every player and the probability it was chosen with
+        let mut path: Vec<(usize, Vec<(usize, f64)>)> = Vec::new();
+        let mut idx = 0;
+        let rewards = loop {
+            let node = &tree[idx];
+            if let Some(condition) = node.state.is_terminal_state() {
+                break node.state.terminal_rewards(&condition);
+            }
+            if node.visits == 0 && idx != 0 {
+                break playout(node.state.clone(), rng);
+            }
+
+            let choices = node
+                .players
+                .iter()
+                .map(|player| self.choose(player, node.visits, rng))
+                .collect::<Vec<_>>();
+            let joint = choices.iter().map(|c| c.0).collect::<Vec<_>>();
+            let child = match node.children.get(&joint) {
+                Some(&child) => child,
+                None => {
+                    let moves = (node.players.iter().zip(&joint))
+                        .map(|(player, &i)| player.moves[i])
+                        .collect::<Vec<_>>();
+                    let child = tree.len();
+                    tree.push(Node::new(node.state.apply_moves(&moves)));
+                    tree[idx].children.insert(joint, child);
+                    child
+                }
+            };
+            path.push((idx, choices));
+            idx = child;
+        };
+
+        tree[idx].visits += 1;
+        for (idx, choices) in path {
+            let node = &mut tree[idx];
+            node.visits += 1;
+            for (p, (player, (i, probability))) in node.players.iter_mut().zip(choices).enumerate()
+            {
+                self.update(player, i, probability, rewards[p]);
+            }
+        }
+    }
+
+    /// Draw the move of `player` at a node visited `visits` times, with the probability it was
+    /// drawn with.
+    fn choose<M>(&self, player: &Player<M>, visits: u32, rng: &mut R) -> (usize, f64) {
+        match self.bandit {
+            Bandit::DecoupledUct { exploration } => {
+                let untried = (0..player.arms.len())
+                    .filter(|&i| player.arms[i].visits == 0)
+                    .collect::<Vec<_>>();
+                if !untried.is_empty() {
+                    return (untried[rng.gen_range(0..untried.len())], 1.0);
+                }
+                let ln = (visits as f64).ln();
+                let i = argmax(player.arms.iter().map(|arm| {
+                    let n = arm.visits as f64;
+                    arm.reward / n + exploration * (ln / n).sqrt()
+                }));
+                (i, 1.0)
+            }
+            Bandit::Exp3 { gamma } | Bandit::RegretMatching { gamma } => {
+                let k = player.arms.len() as f64;
+                let sampling = self
+                    .current_strategy(player)
+                    .into_iter()
+                    .map(|p| (1.0 - gamma) * p + gamma / k)
+                    .collect::<Vec<_>>();
+                let i = rng::weighted_index(sampling.iter().copied(), rng);
+                (i, sampling[i])
+            }
+        }
+    }
+
+    /// The distribution of `player` before uniform exploration, empty for decoupled UCT.
+    fn current_strategy<M>(&self, player: &Player<M>) -> Vec<f64> {
+        let arms = &player.arms;
+        match self.bandit {
+            Bandit::DecoupledUct { .. } => Vec::new(),
+            Bandit::Exp3 { gamma } => {
+                let eta = gamma / arms.len() as f64;
+                let max = arms.iter().map(|a| a.estimate).fold(f64::MIN, f64::max);
+                let weights = arms
+                    .iter()
+                    .map(|a| (eta * (a.estimate - max)).exp())
+                    .collect::<Vec<_>>();
+                let total = weights.iter().sum::<f64>();
+                weights.into_iter().map(|w| w / total).collect()
+            }
+            Bandit::RegretMatching { .. } => {
+                let total = arms.iter().map(|a| a.regret.max(0.0)).sum::<f64>();
+                if total > 0.0 {
+                    arms.iter().map(|a| a.regret.max(0.0) / total).collect()
+                } else {
+                    vec![1.0 / arms.len() as f64; arms.len()]
+                }
+            }
+        }
+    }
+
+    /// Update `player` with the `reward` of move `i`, drawn with `probability`.
+    fn update<M>(&self, player: &mut Player<M>, i: usize, probability: f64, reward: f64) {
+        let strategy = self.current_strategy(player);
+        for (arm, p) in player.arms.iter_mut().zip(&strategy) {
+            arm.strategy += p;
+        }
+        let arm = &mut player.arms[i];
+        arm.visits += 1;
+        arm.reward += reward;
+
+        match self.bandit {
+            Bandit::DecoupledUct { .. } => {}
+            Bandit::Exp3 { .. } => player.arms[i].estimate += reward / probability,
+            Bandit::RegretMatching { .. } => {
+                // regrets against the importance weighted reward of the move drawn
+                let expected = strategy[i] * reward / probability;
+                for (j, arm) in player.arms.iter_mut().enumerate() {
+                    let estimate = if j == i { reward / probability } else { 0.0 };
+                    arm.regret += estimate - expected;
+                }
+            }
+        }
+    }
+
+    /// The final mixed strategy of `player`.
+    fn strategy<M: Copy>(&self, player: &Player<M>) -> MixedStrategy<M> {
+        let weights = match self.bandit {
+            Bandit::DecoupledUct { .. } => player
+                .arms
+                .iter()
+                .map(|a| a.visits as f64)
+                .collect::<Vec<_>>(),
+            _ => player.arms.iter().map(|a| a.strategy).collect(),
+        };
+        let total = weights.iter().sum::<f64>();
+        let moves = player
+            .moves
+            .iter()
+            .zip(weights)
+            .map(|(&m, w)| {
+                let p = if total > 0.0 {
+                    w / total
+                } else {
+                    1.0 / player.moves.len() as f64
+                };
+                (m, p)
+            })
+            .collect();
+        MixedStrategy { moves }
+    }
+}
+
+/// Play `state` out with uniformly random joint moves and return the rewards of every player.
+fn playout<T: SimultaneousState, R: Rng>(mut state: T, rng: &mut R) -> Vec<f64> {
+    loop {
+        if let Some(condition) = state.is_terminal_state() {
+            return state.terminal_rewards(&condition);
+        }
+        let moves = (0..state.num_players())
+            .map(|p| {
+                let moves = state.player_moves(p);
+                moves[rng.gen_range(0..moves.len())]
+            })
+            .collect::<Vec<_>>();
+        state = state.apply_moves(&moves);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bandit, SimultaneousMcts, SimultaneousState};
+    use crate::testing::TestRng;
+
+    /// A single round of a two player game with `moves` moves each, `rewards(a, b)` being the
+    /// rewards of both players when player 0 plays `a` and player 1 plays `b`.
+    #[derive(Clone, Copy)]
+    struct OneShot {
+        moves: usize,
+        rewards: fn(usize, usize) -> Vec<f64>,
+        played: Option<(usize, usize)>,
+    }
+
+    impl SimultaneousState for OneShot {
+        type Move = usize;
+        /// the moves played
+        type UserData = (usize, usize);
+
+        fn player_moves(&self, _player: usize) -> Vec<usize> {
+            (0..self.moves).collect()
+        }
+
+        fn apply_moves(&self, moves: &[usize]) -> Self {
+            Self {
+                played: Some((moves[0], moves[1])),
+                ..*self
+            }
+        }
+
+        fn is_terminal_state(&self) -> Option<(usize, usize)> {
+            self.played
+        }
+
+        fn terminal_rewards(&self, &(a, b): &(usize, usize)) -> Vec<f64> {
+            (self.rewards)(a, b)
+        }
+    }
+
+    /// Rock, paper, scissors: each move beats the one before it.
+    fn rock_paper_scissors() -> OneShot {
+        OneShot {
+            moves: 3,
+            rewards: |a, b| match (3 + a - b) % 3 {
+                0 => vec![0.5, 0.5],
+                1 => vec![1.0, 0.0],
+                _ => vec![0.0, 1.0],
+            },
+            played: None,
+        }
+    }
+
+    fn bandits() -> [Bandit; 3] {
+        [
+            Bandit::default(),
+            Bandit::Exp3 { gamma: 0.1 },
+            Bandit::RegretMatching { gamma: 0.1 },
+        ]
+    }
+
+    /// Zero-sum game in which move 1 is better for either player whatever the other plays.
+    fn dominant_moves() -> OneShot {
+        OneShot {
+            moves: 2,
+            rewards: |a, b| {
+                let reward = [[0.4, 0.2], [0.8, 0.6]][a][b];
+                vec![reward, 1.0 - reward]
+            },
+            played: None,
+        }
+    }
+
+    #[test]
+    fn rock_paper_scissors_strategies_are_near_uniform() {
+        for bandit in bandits() {
+            let result = SimultaneousMcts::<TestRng>::default()
+                .bandit(bandit)
+                .run_with_iterations(rock_paper_scissors(), 100000);
+            for strategy in &result.strategies {
+                for &(_, p) in &strategy.moves {
+                    assert!((p - 1.0 / 3.0).abs() < 0.05, "{bandit:?}: {strategy:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dominant_moves_are_played() {
+        for bandit in bandits() {
+            let result = SimultaneousMcts::<TestRng>::default()
+                .bandit(bandit)
+                .run_with_iterations(dominant_moves(), 20000);
+            for strategy in &result.strategies {
+                assert!(strategy.moves[1].1 > 0.9, "{bandit:?}: {strategy:?}");
+            }
+        }
+    }
+}
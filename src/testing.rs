@@ -0,0 +1,172 @@
+//! Games shared by the unit tests.
+
+use std::ops::Range;
+
+use crate::{
+    rng::{Rng, RngProvider},
+    GameState,
+};
+
+/// Players take turns adding 1 to 3 to a running total, the player reaching `target` wins. The
+/// player to move loses when `target - total` is a multiple of 4.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Nim {
+    pub total: u32,
+    pub target: u32,
+    /// the player who moved into this state
+    pub mover: usize,
+    /// whether states with the same total and mover share a node
+    pub transpositions: bool,
+}
+
+impl Nim {
+    pub fn new(target: u32) -> Self {
+        Self {
+            total: 0,
+            target,
+            mover: 1,
+            transpositions: false,
+        }
+    }
+
+    pub fn with_transpositions(mut self) -> Self {
+        self.transpositions = true;
+        self
+    }
+}
+
+impl GameState for Nim {
+    type Move = u32;
+    /// the winner
+    type UserData = usize;
+
+    fn all_moves(&self) -> Vec<u32> {
+        (1..=3).filter(|n| self.total + n <= self.target).collect()
+    }
+
+    fn apply_move(&self, n: u32) -> Self {
+        Self {
+            total: self.total + n,
+            mover: 1 - self.mover,
+            ..*self
+        }
+    }
+
+    fn is_terminal_state(&self) -> Option<usize> {
+        (self.total == self.target).then_some(self.mover)
+    }
+
+    fn terminal_reward(&self, winner: &usize) -> f64 {
+        if *winner == self.mover {
+            1.0
+        } else {
+            0.0
+        }
+    }
+
+    fn current_player(&self) -> usize {
+        1 - self.mover
+    }
+
+    fn hash(&self) -> Option<u64> {
+        self.transpositions
+            .then_some(self.total as u64 * 2 + self.mover as u64)
+    }
+}
+
+/// Deterministic xorshift generator, so that tests do not depend on the `nanorand` feature.
+pub(crate) struct TestRng(u64);
+
+impl RngProvider for TestRng {
+    fn init() -> Self {
+        TestRng(0x9e37_79b9_7f4a_7c15)
+    }
+}
+
+impl Rng for TestRng {
+    fn gen_range(&mut self, bounds: Range<usize>) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        bounds.start + (self.0 % (bounds.end - bounds.start) as u64) as usize
+    }
+}
+
+/// Players take turns marking an empty cell of a row of five, the player with the most pairs of
+/// adjacent cells marked by them wins once the row is full. Mirrored rows are equivalent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Row {
+    /// 0 for an empty cell, else the player who marked it
+    pub cells: [u8; 5],
+    /// the player who moved into this state
+    pub mover: u8,
+}
+
+impl Row {
+    pub fn new() -> Self {
+        Self {
+            cells: [0; 5],
+            mover: 2,
+        }
+    }
+
+    fn pairs(&self, player: u8) -> usize {
+        self.cells
+            .windows(2)
+            .filter(|w| w[0] == player && w[1] == player)
+            .count()
+    }
+}
+
+impl GameState for Row {
+    type Move = usize;
+    /// the winner, 0 for a draw
+    type UserData = u8;
+
+    fn all_moves(&self) -> Vec<usize> {
+        (0..5).filter(|&i| self.cells[i] == 0).collect()
+    }
+
+    fn apply_move(&self, i: usize) -> Self {
+        assert_eq!(self.cells[i], 0, "cell {i} of {:?} is marked", self.cells);
+        let mut next = Self {
+            cells: self.cells,
+            mover: 3 - self.mover,
+        };
+        next.cells[i] = next.mover;
+        next
+    }
+
+    fn is_terminal_state(&self) -> Option<u8> {
+        if self.cells.contains(&0) {
+            return None;
+        }
+        Some(match self.pairs(1).cmp(&self.pairs(2)) {
+            std::cmp::Ordering::Greater => 1,
+            std::cmp::Ordering::Less => 2,
+            std::cmp::Ordering::Equal => 0,
+        })
+    }
+
+    fn terminal_reward(&self, winner: &u8) -> f64 {
+        match *winner {
+            0 => 0.5,
+            w if w == self.mover => 1.0,
+            _ => 0.0,
+        }
+    }
+
+    fn hash(&self) -> Option<u64> {
+        let cells = self.cells.iter().fold(0, |h, &c| h * 3 + c as u64);
+        Some(cells * 3 + self.mover as u64)
+    }
+
+    fn canonical(&self) -> Self {
+        let mut mirrored = self.cells;
+        mirrored.reverse();
+        Self {
+            cells: self.cells.min(mirrored),
+            mover: self.mover,
+        }
+    }
+}